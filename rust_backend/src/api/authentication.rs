@@ -1,36 +1,71 @@
-use crate::models::auth::{AuthRequest, WebAuthnAuthRequest, WebAuthnVerifyRequest};
+use crate::api::devices::{check_device_access, DeviceAccess};
+use crate::api::tokens::issue_session;
+use crate::config::config::Config;
+use crate::db::collections::{
+    get_passkeys_collection, get_users_collection, get_webauthn_ceremonies_collection,
+    is_duplicate_key_error,
+};
+use crate::models::auth::{
+    AuthRequest, PasskeyLoginFinishRequest, PasskeyLoginStartRequest, WebAuthnVerifyRequest,
+};
+use crate::models::device::Device;
 use crate::models::user::User;
-use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use crate::models::webauthn::{StoredPasskey, WebAuthnCeremony};
+use crate::utils::key_management::encode_hex;
+use crate::utils::logger::log_event;
+use actix_web::{post, web, HttpResponse, Responder};
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use futures::TryStreamExt;
 use log::{error, info};
-use mongodb::{bson::doc, Client, Collection};
+use mongodb::{
+    bson::{doc, DateTime},
+    Client, Collection,
+};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::json;
-use url::Url;
 use uuid::Uuid;
-use webauthn_rs::prelude::{PasskeyRegistration, RegisterPublicKeyCredential};
-use webauthn_rs::proto::{AuthenticatorSelectionCriteria, UserVerificationRequirement};
-use webauthn_rs::WebauthnBuilder;
-
-/// Extracts the origin dynamically from the incoming request.
-fn get_rp_origin(req: &HttpRequest) -> Result<Url, String> {
-    if let Some(conn_info) = req.connection_info().host().split_once(':') {
-        let scheme = if req.connection_info().scheme() == "https" {
-            "https"
-        } else {
-            "http"
-        };
-        let origin = format!("{}://{}", scheme, conn_info.0);
-        Url::parse(&origin).map_err(|e| format!("Failed to parse URL: {}", e))
-    } else {
-        Err("Unable to extract host from request".to_string())
-    }
-}
+use webauthn_rs::prelude::{Passkey, PasskeyAuthentication, PasskeyRegistration};
+use webauthn_rs::{Webauthn, WebauthnBuilder};
+
+/// How long a started WebAuthn ceremony may take before it must be restarted
+const CEREMONY_TTL_SECS: i64 = 300;
 
 #[post("/login")]
-async fn login(db_client: web::Data<Client>, req: web::Json<AuthRequest>) -> impl Responder {
+async fn login(
+    db_client: web::Data<Client>,
+    config: web::Data<Config>,
+    req: web::Json<AuthRequest>,
+) -> impl Responder {
     match authenticate_user(&db_client, &req.username, &req.password).await {
-        Ok(true) => HttpResponse::Ok().json("✅ Login successful"),
-        Ok(false) => HttpResponse::Unauthorized().json("❌ Invalid credentials"),
+        Ok(Some(user)) => {
+            // Tokens are only issued to devices approved for the account.
+            match check_device_access(&db_client, &config, &user.user_id, &req.device_id).await {
+                Ok(DeviceAccess::Active(_)) => {}
+                Ok(DeviceAccess::ReapprovalRequired) => {
+                    return HttpResponse::Forbidden().json("❌ Device reapproval required");
+                }
+                Ok(DeviceAccess::PendingApproval) => {
+                    return HttpResponse::Forbidden().json("❌ Device awaiting approval");
+                }
+                Ok(DeviceAccess::Unknown) => {
+                    info!("Login for '{}' from unrecognized device.", req.username);
+                    return HttpResponse::Unauthorized().json("❌ Unrecognized device");
+                }
+                Err(e) => {
+                    error!("Login error: {}", e);
+                    return HttpResponse::InternalServerError().json("❌ Internal server error");
+                }
+            }
+
+            match issue_session(&db_client, &config, &user.user_id, &req.device_id, None).await {
+                Ok(tokens) => HttpResponse::Ok().json(tokens),
+                Err(e) => {
+                    error!("Login error: {}", e);
+                    HttpResponse::InternalServerError().json("❌ Internal server error")
+                }
+            }
+        }
+        Ok(None) => HttpResponse::Unauthorized().json("❌ Invalid credentials"),
         Err(e) => {
             error!("Login error: {}", e);
             HttpResponse::InternalServerError().json("❌ Internal server error")
@@ -39,19 +74,19 @@ async fn login(db_client: web::Data<Client>, req: web::Json<AuthRequest>) -> imp
 }
 
 /// **Asynchronous user authentication function**  
-/// Verifies user credentials against the database.
+/// Verifies user credentials against the database and returns the matching user.
 pub async fn authenticate_user(
     db_client: &Client,
     username: &str,
     password: &str,
-) -> Result<bool, String> {
+) -> Result<Option<User>, String> {
     let db = db_client.database("valutx"); // Replace with your DB name
     let users_collection: Collection<User> = db.collection("users");
 
     // Find user by username
     let filter = doc! { "username": username };
     let user_result = users_collection
-        .find_one(filter, None)
+        .find_one(filter)
         .await
         .map_err(|e| format!("Database query failed: {}", e))?;
 
@@ -64,63 +99,157 @@ pub async fn authenticate_user(
         match argon2.verify_password(password.as_bytes(), &parsed_hash) {
             Ok(_) => {
                 info!("User '{}' authenticated successfully.", username);
-                Ok(true)
+                Ok(Some(user))
             }
             Err(_) => {
                 info!("Invalid credentials for user '{}'.", username);
-                Ok(false)
+                Ok(None)
             }
         }
     } else {
         info!("User '{}' not found.", username);
-        Ok(false)
+        Ok(None)
     }
 }
 
-#[post("/register")]
+/// **Builds the shared WebAuthn instance from the configured relying party**
+/// Called once at startup; the instance is injected into handlers via `web::Data`.
+pub fn build_webauthn(config: &Config) -> Result<Webauthn, String> {
+    let (primary_origin, extra_origins) = config
+        .rp_origins
+        .split_first()
+        .ok_or_else(|| "No WebAuthn origins configured".to_string())?;
+
+    let mut builder = WebauthnBuilder::new(&config.rp_id, primary_origin)
+        .map_err(|e| format!("WebauthnBuilder creation failed: {}", e))?
+        .rp_name(&config.rp_name);
+    for origin in extra_origins {
+        builder = builder.append_allowed_origin(origin);
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("WebAuthn build failed: {}", e))
+}
+
+/// Persists ceremony state server-side and returns the id the client echoes back.
+async fn save_ceremony<T: Serialize>(
+    client: &Client,
+    user_id: &str,
+    kind: &str,
+    state: &T,
+) -> Result<String, String> {
+    let ceremony = WebAuthnCeremony {
+        passkey_session_id: Uuid::new_v4().to_string(),
+        user_id: user_id.to_string(),
+        kind: kind.to_string(),
+        state: serde_json::to_string(state)
+            .map_err(|e| format!("Failed to serialize ceremony state: {}", e))?,
+        expires_at: DateTime::from_millis(
+            DateTime::now().timestamp_millis() + CEREMONY_TTL_SECS * 1000,
+        ),
+    };
+
+    let db = client.database("valutx");
+    get_webauthn_ceremonies_collection(&db)
+        .insert_one(&ceremony)
+        .await
+        .map_err(|e| format!("Failed to store ceremony state: {}", e))?;
+
+    Ok(ceremony.passkey_session_id)
+}
+
+/// Removes and returns unexpired ceremony state, so each challenge can only be answered once.
+async fn take_ceremony<T: DeserializeOwned>(
+    client: &Client,
+    passkey_session_id: &str,
+    kind: &str,
+) -> Result<Option<(String, T)>, String> {
+    let db = client.database("valutx");
+    let ceremony = get_webauthn_ceremonies_collection(&db)
+        .find_one_and_delete(doc! {
+            "passkey_session_id": passkey_session_id,
+            "kind": kind,
+            "expires_at": { "$gt": DateTime::now() },
+        })
+        .await
+        .map_err(|e| format!("Failed to load ceremony state: {}", e))?;
+
+    match ceremony {
+        Some(ceremony) => {
+            let state = serde_json::from_str(&ceremony.state)
+                .map_err(|e| format!("Failed to parse ceremony state: {}", e))?;
+            Ok(Some((ceremony.user_id, state)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Loads every passkey registered to a user.
+async fn load_passkeys(client: &Client, user_id: &str) -> Result<Vec<Passkey>, String> {
+    let db = client.database("valutx");
+    let stored: Vec<StoredPasskey> = get_passkeys_collection(&db)
+        .find(doc! { "user_id": user_id })
+        .await
+        .map_err(|e| format!("Failed to load passkeys: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to load passkeys: {}", e))?;
+
+    stored
+        .iter()
+        .map(|p| {
+            serde_json::from_str(&p.passkey).map_err(|e| format!("Failed to parse passkey: {}", e))
+        })
+        .collect()
+}
+
+/// **Starts passkey registration for the authenticated user**
+/// The registration state stays on the server; the client receives the full
+/// creation options and a `passkey_session_id` to send back with its answer.
+#[post("/webauthn/register/start")]
 async fn register_webauthn(
-    req: HttpRequest,
-    payload: web::Json<WebAuthnAuthRequest>,
+    webauthn: web::Data<Webauthn>,
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
 ) -> impl Responder {
-    // Extract rp_origin dynamically from the request
-    let rp_origin = match get_rp_origin(&req) {
-        Ok(origin) => origin,
+    let db = client.database("valutx");
+    let user = match get_users_collection(&db)
+        .find_one(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().json("❌ User not found"),
         Err(e) => {
-            error!("Origin extraction failed: {}", e);
-            return HttpResponse::InternalServerError().json("❌ Failed to parse request origin");
+            error!("Failed to load user: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
         }
     };
 
-    // Initialize WebAuthnBuilder with proper error handling
-    let webauthn = match WebauthnBuilder::new("ValutX", &rp_origin)
-        .map_err(|e| format!("WebauthnBuilder creation failed: {}", e))
-        .and_then(|builder| {
-            builder
-                .build()
-                .map_err(|e| format!("WebAuthn build failed: {}", e))
-        }) {
-        Ok(webauthn) => webauthn,
+    // The WebAuthn user handle is the account's stable user_id
+    let user_handle = match Uuid::parse_str(&user.user_id) {
+        Ok(uuid) => uuid,
         Err(e) => {
-            error!("{}", e);
-            return HttpResponse::InternalServerError().json("❌ WebAuthn initialization failed");
+            error!("User id is not a UUID: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
         }
     };
 
-    // Generate a new Uuid for the user handle
-    let user_handle_uuid = Uuid::new_v4();
-
-    // Set up authenticator selection criteria with user verification requirement
-    let auth_selection = AuthenticatorSelectionCriteria {
-        user_verification: UserVerificationRequirement::Preferred,
-        ..Default::default()
+    // Don't let the same authenticator register twice
+    let existing = match load_passkeys(&client, &user.user_id).await {
+        Ok(passkeys) => passkeys,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
     };
+    let exclude_credentials = Some(existing.iter().map(|p| p.cred_id().clone()).collect());
 
-    // Start passkey registration with proper arguments
-    let (ccr, _registration) = match webauthn.start_passkey_registration(
-        user_handle_uuid,
-        &payload.user_id,
-        &auth_selection,
-        None, // No excluded credentials
+    let (ccr, registration) = match webauthn.start_passkey_registration(
+        user_handle,
+        &user.username,
+        &user.username,
+        exclude_credentials,
     ) {
         Ok(result) => result,
         Err(e) => {
@@ -130,69 +259,258 @@ async fn register_webauthn(
         }
     };
 
-    // Return the challenge from CreationChallengeResponse
-    HttpResponse::Ok().json(json!({ "challenge": ccr.challenge }))
+    match save_ceremony(&client, &user.user_id, "registration", &registration).await {
+        Ok(passkey_session_id) => HttpResponse::Ok().json(json!({
+            "passkey_session_id": passkey_session_id,
+            "options": ccr,
+        })),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to start passkey registration")
+        }
+    }
 }
 
-#[post("/verify")]
+/// **Finishes passkey registration and stores the new passkey**
+#[post("/webauthn/register/finish")]
 async fn verify_webauthn(
-    http_req: HttpRequest,
+    webauthn: web::Data<Webauthn>,
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
     req: web::Json<WebAuthnVerifyRequest>,
 ) -> impl Responder {
-    // Extract rp_origin dynamically from the request
-    let rp_origin = match get_rp_origin(&http_req) {
-        Ok(origin) => origin,
+    let passkey_registration = match take_ceremony::<PasskeyRegistration>(
+        &client,
+        &req.passkey_session_id,
+        "registration",
+    )
+    .await
+    {
+        Ok(Some((user_id, state))) if user_id == claims.user_id => state,
+        Ok(_) => return HttpResponse::BadRequest().json("❌ Unknown or expired passkey session"),
         Err(e) => {
-            error!("Origin extraction failed: {}", e);
-            return HttpResponse::InternalServerError().json("❌ Failed to parse request origin");
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
         }
     };
 
-    // Initialize WebAuthnBuilder with proper error handling
-    let webauthn = match WebauthnBuilder::new("ValutX", &rp_origin)
-        .map_err(|e| format!("WebauthnBuilder creation failed: {}", e))
-        .and_then(|builder| {
-            builder
-                .build()
-                .map_err(|e| format!("WebAuthn build failed: {}", e))
-        }) {
-        Ok(webauthn) => webauthn,
+    let passkey = match webauthn.finish_passkey_registration(&req.credential, &passkey_registration)
+    {
+        Ok(passkey) => passkey,
+        Err(e) => {
+            error!("Biometric verification failed: {}", e);
+            return HttpResponse::Unauthorized().json("❌ Failed biometric authentication");
+        }
+    };
+
+    let serialized = match serde_json::to_string(&passkey) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            error!("Failed to serialize passkey: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let stored = StoredPasskey {
+        user_id: claims.user_id.clone(),
+        credential_id: encode_hex(passkey.cred_id()),
+        passkey: serialized,
+        created_at: DateTime::now(),
+        last_used_at: None,
+    };
+
+    let db = client.database("valutx");
+    match get_passkeys_collection(&db).insert_one(&stored).await {
+        Ok(_) => {
+            log_event(
+                &client,
+                &claims.user_id,
+                "passkey_registered",
+                &format!("Credential {} from device {}", stored.credential_id, claims.device_id),
+            )
+            .await;
+            HttpResponse::Ok().json(json!({ "credential_id": stored.credential_id }))
+        }
+        Err(e) if is_duplicate_key_error(&e) => {
+            HttpResponse::Conflict().json("❌ Passkey already registered")
+        }
+        Err(e) => {
+            error!("Failed to store passkey: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
+
+/// **Starts a passkey login for a username**
+/// Returns the assertion options for the user's registered passkeys.
+#[post("/webauthn/login/start")]
+async fn start_passkey_login(
+    webauthn: web::Data<Webauthn>,
+    client: web::Data<Client>,
+    payload: web::Json<PasskeyLoginStartRequest>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    let user = match get_users_collection(&db)
+        .find_one(doc! { "username": &payload.username })
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            info!("Passkey login for unknown user '{}'.", payload.username);
+            return HttpResponse::Unauthorized().json("❌ Passkey login unavailable");
+        }
+        Err(e) => {
+            error!("Failed to load user: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let passkeys = match load_passkeys(&client, &user.user_id).await {
+        Ok(passkeys) if !passkeys.is_empty() => passkeys,
+        Ok(_) => return HttpResponse::Unauthorized().json("❌ Passkey login unavailable"),
         Err(e) => {
             error!("{}", e);
-            return HttpResponse::InternalServerError().json("❌ WebAuthn initialization failed");
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
         }
     };
 
-    // Convert req.credential_id and req.signature into expected types
-    let register_public_key_credential =
-        match serde_json::from_str::<RegisterPublicKeyCredential>(&req.credential_id) {
-            Ok(cred) => cred,
-            Err(e) => {
-                error!("Failed to parse RegisterPublicKeyCredential: {}", e);
-                return HttpResponse::BadRequest().json("❌ Invalid credential data");
-            }
-        };
+    let (rcr, authentication) = match webauthn.start_passkey_authentication(&passkeys) {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Passkey authentication failed to start: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to start passkey login");
+        }
+    };
 
-    #[derive(serde::Deserialize)]
-    let passkey_registration = match serde_json::from_str::<PasskeyRegistration>(&req.signature) {
-        Ok(sig) => sig,
+    match save_ceremony(&client, &user.user_id, "authentication", &authentication).await {
+        Ok(passkey_session_id) => HttpResponse::Ok().json(json!({
+            "passkey_session_id": passkey_session_id,
+            "options": rcr,
+        })),
         Err(e) => {
-            error!("Failed to parse PasskeyRegistration: {}", e);
-            return HttpResponse::BadRequest().json("❌ Invalid signature data");
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to start passkey login")
+        }
+    }
+}
+
+/// **Finishes a passkey login and issues a session**
+/// Verifies the assertion, persists the updated signature counter and
+/// returns the same tokens as password login.
+#[post("/webauthn/login/finish")]
+async fn finish_passkey_login(
+    webauthn: web::Data<Webauthn>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    req: web::Json<PasskeyLoginFinishRequest>,
+) -> impl Responder {
+    let (user_id, authentication) = match take_ceremony::<PasskeyAuthentication>(
+        &client,
+        &req.passkey_session_id,
+        "authentication",
+    )
+    .await
+    {
+        Ok(Some(ceremony)) => ceremony,
+        Ok(None) => return HttpResponse::BadRequest().json("❌ Unknown or expired passkey session"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
         }
     };
 
-    // Finish passkey registration with the correct arguments
-    let result = webauthn.finish_passkey_registration(
-        &register_public_key_credential, // Correct type
-        &passkey_registration,           // Correct type
-    );
+    let result = match webauthn.finish_passkey_authentication(&req.credential, &authentication) {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Passkey authentication failed: {}", e);
+            log_event(&client, &user_id, "passkey_login_failed", &e.to_string()).await;
+            return HttpResponse::Unauthorized().json("❌ Failed biometric authentication");
+        }
+    };
 
-    match result {
-        Ok(_) => HttpResponse::Ok().json("✅ Biometric authentication successful"),
+    // Persist the new signature counter so cloned authenticators can be detected
+    let db = client.database("valutx");
+    let passkeys = get_passkeys_collection(&db);
+    let credential_id = encode_hex(result.cred_id());
+    let filter = doc! { "user_id": &user_id, "credential_id": &credential_id };
+    let stored = match passkeys.find_one(filter.clone()).await {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return HttpResponse::Unauthorized().json("❌ Unknown passkey"),
         Err(e) => {
-            error!("Biometric verification failed: {}", e);
-            HttpResponse::Unauthorized().json("❌ Failed biometric authentication")
+            error!("Failed to load passkey: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let mut passkey: Passkey = match serde_json::from_str(&stored.passkey) {
+        Ok(passkey) => passkey,
+        Err(e) => {
+            error!("Failed to parse passkey: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+    passkey.update_credential(&result);
+
+    let serialized = match serde_json::to_string(&passkey) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            error!("Failed to serialize passkey: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+    if let Err(e) = passkeys
+        .update_one(
+            filter,
+            doc! { "$set": { "passkey": serialized, "last_used_at": DateTime::now() } },
+        )
+        .await
+    {
+        error!("Failed to update passkey: {}", e);
+        return HttpResponse::InternalServerError().json("❌ Internal server error");
+    }
+
+    let user = match get_users_collection(&db).find_one(doc! { "user_id": &user_id }).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::Unauthorized().json("❌ Invalid credentials"),
+        Err(e) => {
+            error!("Failed to load user: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    // Same device rule as password login
+    match check_device_access(&client, &config, &user.user_id, &req.device_id).await {
+        Ok(DeviceAccess::Active(_)) => {}
+        Ok(DeviceAccess::ReapprovalRequired) => {
+            return HttpResponse::Forbidden().json("❌ Device reapproval required");
+        }
+        Ok(DeviceAccess::PendingApproval) => {
+            return HttpResponse::Forbidden().json("❌ Device awaiting approval");
+        }
+        Ok(DeviceAccess::Unknown) => {
+            info!("Passkey login for '{}' from unrecognized device.", user.username);
+            return HttpResponse::Unauthorized().json("❌ Unrecognized device");
+        }
+        Err(e) => {
+            error!("Login error: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
+
+    match issue_session(&client, &config, &user.user_id, &req.device_id, None).await {
+        Ok(tokens) => {
+            log_event(
+                &client,
+                &user.user_id,
+                "passkey_login",
+                &format!("Credential {} on device {}", credential_id, req.device_id),
+            )
+            .await;
+            HttpResponse::Ok().json(tokens)
+        }
+        Err(e) => {
+            error!("Login error: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
         }
     }
 }
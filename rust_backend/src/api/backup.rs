@@ -1,90 +1,264 @@
-use crate::models::encryption::{BackupRequest, RestoreRequest};
+use crate::api::sync::next_revision;
+use crate::db::collections::{
+    get_records_collection, get_users_collection, get_vault_keys_collection, is_duplicate_key_error,
+};
+use crate::models::device::Device;
+use crate::models::encryption::{BackupRecord, VaultBackup, BACKUP_FORMAT_VERSION};
+use crate::models::key::VaultKey;
 use crate::models::record::Record;
-use crate::utils::encryption::{decrypt_data, encrypt_data};
-use actix_web::{post, web, HttpResponse, Responder};
+use crate::utils::logger::log_event;
+use actix_web::{get, post, web, HttpResponse, Responder};
 use futures::TryStreamExt;
-use mongodb::{bson, options::FindOptions, Client};
+use log::error;
+use mongodb::{
+    bson::{doc, to_bson, DateTime},
+    Client,
+};
+use serde_json::json;
+use std::collections::HashSet;
 
-#[post("/backup")]
-async fn backup(client: web::Data<Client>, req: web::Json<BackupRequest>) -> impl Responder {
-    let db = client.database("valutx");
-    let key = req.encryption_key.as_bytes();
-
-    if key.len() != 32 {
-        return HttpResponse::BadRequest().body("Encryption key must be exactly 32 bytes");
-    }
+/// Largest backup accepted for restore
+pub const MAX_BACKUP_BYTES: usize = 32 * 1024 * 1024;
 
-    let collection = db.collection::<Record>("records");
-    let cursor = match collection.find(None, FindOptions::default()).await {
-        Ok(cursor) => cursor,
+/// **Exports the caller's live records with their wrapped keys**
+/// Nothing is decrypted or re-encrypted here: the backup is the same ciphertext
+/// the server already stores. It carries the KDF parameters, so the master
+/// password alone opens it.
+#[get("")]
+async fn export_backup(client: web::Data<Client>, claims: web::ReqData<Device>) -> impl Responder {
+    let db = client.database("valutx");
+    let kdf = match get_users_collection(&db)
+        .find_one(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(Some(user)) => match user.kdf {
+            Some(kdf) => kdf,
+            None => return HttpResponse::NotFound().json("❌ No key derivation parameters stored"),
+        },
+        Ok(None) => return HttpResponse::NotFound().json("❌ User not found"),
         Err(e) => {
-            eprintln!("Failed to fetch records: {}", e);
-            return HttpResponse::InternalServerError().body("Backup failed");
+            error!("Failed to load user: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Backup failed");
         }
     };
-
-    let records: Vec<Record> = match cursor.try_collect().await {
-        Ok(records) => records,
+    let vault_key = match get_vault_keys_collection(&db)
+        .find_one(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(Some(key)) => key,
+        Ok(None) => return HttpResponse::NotFound().json("❌ No vault key stored"),
         Err(e) => {
-            eprintln!("Error collecting records: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to collect records");
+            error!("Failed to fetch vault key: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Backup failed");
         }
     };
 
-    let serialized = match bson::to_vec(&records) {
-        Ok(data) => data,
+    let cursor = match get_records_collection(&db)
+        .find(doc! { "user_id": &claims.user_id, "deleted_at": null })
+        .await
+    {
+        Ok(cursor) => cursor,
         Err(e) => {
-            eprintln!("Serialization error: {}", e);
-            return HttpResponse::InternalServerError().body("Backup failed");
+            error!("Failed to fetch records: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Backup failed");
         }
     };
-
-    let encrypted = match encrypt_data(&String::from_utf8_lossy(&serialized), key) {
-        Ok(data) => data,
+    let records: Vec<Record> = match cursor.try_collect().await {
+        Ok(records) => records,
         Err(e) => {
-            eprintln!("Encryption failed: {}", e);
-            return HttpResponse::InternalServerError().body("Backup failed");
+            error!("Failed to collect records: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Backup failed");
         }
     };
 
-    HttpResponse::Ok().body(encrypted)
+    let backup = VaultBackup {
+        format_version: BACKUP_FORMAT_VERSION,
+        kdf,
+        wrapped_vault_key: vault_key.wrapped_key,
+        records: records
+            .into_iter()
+            .map(|record| BackupRecord {
+                id: record.id,
+                record_type: record.record_type,
+                title: record.title,
+                metadata: record.metadata,
+                encrypted_data: record.encrypted_data,
+                wrapped_item_key: record.wrapped_item_key,
+            })
+            .collect(),
+    };
+
+    log_event(
+        &client,
+        &claims.user_id,
+        "vault_exported",
+        &format!("{} record(s) exported from device {}", backup.records.len(), claims.device_id),
+    )
+    .await;
+
+    HttpResponse::Ok().json(backup)
 }
 
+/// **Restores records from a backup**
+/// The backup must carry the caller's vault key, or none must be stored yet, since
+/// its item keys only unwrap under that vault key. In the latter case the backup's
+/// vault key and KDF parameters are adopted, so the backup's master password
+/// unlocks the account. Records that already exist are skipped; restored ones
+/// come back unfiled and untagged. Ids already used by another account are
+/// returned as `collisions` and not restored. Records exported before item
+/// keys existed are accepted without one.
 #[post("/restore")]
-async fn restore(client: web::Data<Client>, req: web::Json<RestoreRequest>) -> impl Responder {
-    let db = client.database("valutx");
-    let key = req.encryption_key.as_bytes();
+async fn restore_backup(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    req: web::Json<VaultBackup>,
+) -> impl Responder {
+    let backup = req.into_inner();
+    if backup.format_version != BACKUP_FORMAT_VERSION {
+        return HttpResponse::BadRequest().json("❌ Unsupported backup format");
+    }
+    if let Err(e) = backup.wrapped_vault_key.validate().and_then(|_| backup.kdf.validate()) {
+        return HttpResponse::BadRequest().json(format!("❌ {}", e));
+    }
 
-    if key.len() != 32 {
-        return HttpResponse::BadRequest().body("Encryption key must be exactly 32 bytes");
+    let db = client.database("valutx");
+    let vault_keys = get_vault_keys_collection(&db);
+    match vault_keys.find_one(doc! { "user_id": &claims.user_id }).await {
+        Ok(Some(key)) if key.wrapped_key != backup.wrapped_vault_key => {
+            return HttpResponse::Conflict().json("❌ Backup was made with a different vault key");
+        }
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let kdf = match to_bson(&backup.kdf) {
+                Ok(kdf) => kdf,
+                Err(e) => {
+                    error!("Failed to encode KDF parameters: {}", e);
+                    return HttpResponse::InternalServerError().json("❌ Restore failed");
+                }
+            };
+            // The key goes in first and its unique index settles a race with another
+            // restore; the KDF parameters only change once this one has won it
+            let now = DateTime::now();
+            let key = VaultKey {
+                user_id: claims.user_id.clone(),
+                wrapped_key: backup.wrapped_vault_key.clone(),
+                key_version: 1,
+                created_at: now,
+                updated_at: now,
+            };
+            match vault_keys.insert_one(&key).await {
+                Ok(_) => {}
+                Err(e) if is_duplicate_key_error(&e) => {
+                    return HttpResponse::Conflict().json("❌ Vault key already exists");
+                }
+                Err(e) => {
+                    error!("Failed to store vault key: {}", e);
+                    return HttpResponse::InternalServerError().json("❌ Restore failed");
+                }
+            }
+            if let Err(e) = get_users_collection(&db)
+                .update_one(doc! { "user_id": &claims.user_id }, doc! { "$set": { "kdf": kdf } })
+                .await
+            {
+                error!("Failed to store KDF parameters: {}", e);
+                // Without its KDF parameters the key can't be unwrapped, so take it back out
+                if let Err(e) = vault_keys.delete_one(doc! { "user_id": &claims.user_id }).await {
+                    error!("Failed to remove vault key: {}", e);
+                }
+                return HttpResponse::InternalServerError().json("❌ Restore failed");
+            }
+        }
+        Err(e) => {
+            error!("Failed to fetch vault key: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Restore failed");
+        }
     }
 
-    let decrypted = match decrypt_data(&req.backup_data, key) {
-        Ok(data) => data,
+    let lease = match next_revision(&client, &claims.user_id).await {
+        Ok(lease) => lease,
         Err(e) => {
-            eprintln!("Decryption failed: {}", e);
-            return HttpResponse::InternalServerError().body("Restore failed");
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Restore failed");
         }
     };
+    let revision = lease.revision();
+    let now = DateTime::now();
+    let records: Vec<Record> = backup
+        .records
+        .into_iter()
+        .map(|entry| Record {
+            id: entry.id,
+            user_id: claims.user_id.clone(),
+            record_type: entry.record_type,
+            title: entry.title,
+            metadata: entry.metadata,
+            encrypted_data: entry.encrypted_data,
+            wrapped_item_key: entry.wrapped_item_key,
+            folder_id: None,
+            tag_ids: Vec::new(),
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            revision,
+            created_revision: revision,
+            version: 1,
+        })
+        .collect();
+    if let Some(e) = records.iter().find_map(|record| record.validate_fields().err()) {
+        return HttpResponse::BadRequest().json(format!("❌ {}", e));
+    }
 
-    let records: Vec<Record> = match bson::from_slice(decrypted.as_bytes()) {
-        Ok(docs) => docs,
+    let collection = get_records_collection(&db);
+    let ids: Vec<&str> = records.iter().map(|record| record.id.as_str()).collect();
+    let taken: Vec<Record> = match collection.find(doc! { "_id": { "$in": &ids } }).await {
+        Ok(cursor) => match cursor.try_collect().await {
+            Ok(found) => found,
+            Err(e) => {
+                error!("Failed to collect records: {}", e);
+                return HttpResponse::InternalServerError().json("❌ Restore failed");
+            }
+        },
         Err(e) => {
-            eprintln!("Deserialization failed: {}", e);
-            return HttpResponse::InternalServerError().body("Restore failed");
+            error!("Failed to fetch records: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Restore failed");
         }
     };
+    // The caller's own copies are skipped; ids held by another account can't be restored
+    let (existing, collisions): (Vec<Record>, Vec<Record>) =
+        taken.into_iter().partition(|record| record.user_id == claims.user_id);
+    let existing: HashSet<String> = existing.into_iter().map(|record| record.id).collect();
+    let collisions: Vec<String> = collisions.into_iter().map(|record| record.id).collect();
 
-    let collection = db.collection::<Record>("records");
-    if let Err(e) = collection.insert_many(records, None).await {
-        eprintln!("Failed to insert records: {}", e);
-        return HttpResponse::InternalServerError().body("Restore failed");
+    let missing: Vec<Record> = records
+        .into_iter()
+        .filter(|record| !existing.contains(&record.id) && !collisions.contains(&record.id))
+        .collect();
+    let restored = missing.len();
+    if restored > 0 {
+        if let Err(e) = collection.insert_many(&missing).await {
+            error!("Failed to insert records: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Restore failed");
+        }
+        lease.commit(Some(&claims.device_id)).await;
     }
 
-    HttpResponse::Ok().body("Database restored successfully")
-}
+    log_event(
+        &client,
+        &claims.user_id,
+        "vault_restored",
+        &format!(
+            "{} record(s) restored from backup by device {}, {} already present, {} not restored because the id is in use",
+            restored,
+            claims.device_id,
+            existing.len(),
+            collisions.len()
+        ),
+    )
+    .await;
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(backup);
-    cfg.service(restore);
+    HttpResponse::Ok().json(json!({
+        "restored": restored,
+        "skipped": existing.len(),
+        "collisions": collisions,
+    }))
 }
@@ -1,79 +1,725 @@
-use actix_web::{post, web, HttpResponse, Responder};
-use mongodb::{bson::doc, Client, Collection};
-use crate::models::device::Device;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse, Responder};
+use futures::TryStreamExt;
+use log::{error, warn};
+use mongodb::{
+    bson::{doc, Bson, DateTime, Document},
+    options::ReturnDocument,
+    Client,
+};
+use crate::api::sessions::revoke_sessions;
+use crate::config::config::Config;
+use crate::db::collections::{
+    find_page, get_device_pairings_collection, get_devices_collection, get_users_collection,
+    is_duplicate_key_error, PageSpec,
+};
+use crate::models::device::{
+    Device, DeviceFingerprint, DeviceRegistrationRequest, DeviceRenameRequest, DeviceStatus,
+    TrustedDevice,
+};
+use crate::models::event::VaultEvent;
+use crate::models::pagination::PageQuery;
+use crate::models::pairing::{DevicePairing, PairingStatus};
+use crate::utils::events::publish;
+use crate::utils::fingerprint::{observe, to_stored};
+use crate::utils::hashing::hash_token;
+use crate::utils::key_management::{generate_pairing_code, normalize_code};
+use crate::utils::logger::log_event;
+use serde_json::json;
+use url::Url;
+use uuid::Uuid;
 
-#[post("/devices/preapprove")]
-pub async fn preapprove_device(
+/// Whether a device may currently be used
+pub enum DeviceAccess {
+    Active(Box<TrustedDevice>),
+    /// Known but expired (idle too long); must be approved again
+    ReapprovalRequired,
+    /// Registered but not yet approved by a trusted device
+    PendingApproval,
+    Unknown,
+}
+
+/// Whether a device was last active more than `idle_secs` before `now`.
+/// Devices that were never used count from their approval time.
+fn is_idle(device: &TrustedDevice, idle_secs: i64, now: DateTime) -> bool {
+    let idle_cutoff = now.timestamp_millis() - idle_secs * 1000;
+    device
+        .last_seen_at
+        .or(device.approved_at)
+        .is_some_and(|at| at.timestamp_millis() < idle_cutoff)
+}
+
+/// **Checks that a device is approved and hasn't been idle past the window**
+/// A device found idle here is expired on the spot rather than waiting for the sweeper.
+pub async fn check_device_access(
+    client: &Client,
+    config: &Config,
+    user_id: &str,
+    device_id: &str,
+) -> Result<DeviceAccess, String> {
+    let db = client.database("valutx");
+    let device = match get_devices_collection(&db)
+        .find_one(doc! { "user_id": user_id, "device_id": device_id })
+        .await
+        .map_err(|e| format!("Failed to load device: {}", e))?
+    {
+        Some(device) => device,
+        None => return Ok(DeviceAccess::Unknown),
+    };
+
+    match device.status {
+        DeviceStatus::Approved => {}
+        DeviceStatus::Expired => return Ok(DeviceAccess::ReapprovalRequired),
+        DeviceStatus::Pending => return Ok(DeviceAccess::PendingApproval),
+    }
+
+    if is_idle(&device, config.device_idle_secs, DateTime::now()) {
+        expire_device(client, user_id, device_id).await?;
+        return Ok(DeviceAccess::ReapprovalRequired);
+    }
+
+    Ok(DeviceAccess::Active(Box::new(device)))
+}
+
+/// Records that a device was just used.
+pub async fn touch_device(client: &Client, user_id: &str, device_id: &str) -> Result<(), String> {
+    let db = client.database("valutx");
+    get_devices_collection(&db)
+        .update_one(
+            doc! { "user_id": user_id, "device_id": device_id },
+            doc! { "$set": { "last_seen_at": DateTime::now() } },
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Failed to update device last_seen_at: {}", e))
+}
+
+/// **Marks a device expired, ends its sessions and logs it**
+pub async fn expire_device(client: &Client, user_id: &str, device_id: &str) -> Result<(), String> {
+    let db = client.database("valutx");
+    let result = get_devices_collection(&db)
+        .update_one(
+            doc! { "user_id": user_id, "device_id": device_id, "status": "approved" },
+            doc! { "$set": { "status": "expired" } },
+        )
+        .await
+        .map_err(|e| format!("Failed to expire device: {}", e))?;
+
+    if result.modified_count > 0 {
+        warn!("Device '{}' of user '{}' expired after inactivity.", device_id, user_id);
+        revoke_sessions(client, doc! { "user_id": user_id, "device_id": device_id }).await?;
+        log_event(
+            client,
+            user_id,
+            "device_expired",
+            &format!("Device {} idle past the inactivity window; reapproval required", device_id),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Longest friendly name accepted for a device
+const DEVICE_NAME_MAX_LEN: usize = 64;
+
+/// How long a pairing code stays valid, from issue to approval
+const PAIRING_TTL_SECS: i64 = 300;
+
+/// Code submissions allowed per pairing before it stops accepting any
+const PAIRING_MAX_ATTEMPTS: i32 = 5;
+
+fn format_date(date: Option<DateTime>) -> Option<String> {
+    date.and_then(|date| date.try_to_rfc3339_string().ok())
+}
+
+/// Trims a requested device name, or `None` if it is empty or too long.
+fn device_name(name: &str) -> Option<&str> {
+    let name = name.trim();
+    (!name.is_empty() && name.chars().count() <= DEVICE_NAME_MAX_LEN).then_some(name)
+}
+
+/// Only a prefix of the hardware hash is shown; enough to tell devices apart.
+fn fingerprint_summary(fingerprint: &DeviceFingerprint) -> serde_json::Value {
+    json!({
+        "hardware": fingerprint
+            .hardware_hash
+            .as_ref()
+            .map(|hash| hash.chars().take(12).collect::<String>()),
+        "ip_network": fingerprint.ip_network,
+        "tls_bound": fingerprint.tls_fingerprint.is_some(),
+    })
+}
+
+/// Sort keys accepted by the device listing; `registered` is insertion order
+const DEVICE_SORTS: &[(&str, &str)] = &[("device_id", "device_id"), ("registered", "_id")];
+
+/// Lists the caller's devices a page at a time, with their fingerprint summary and activity.
+#[get("/devices")]
+pub async fn list_devices(
     client: web::Data<Client>,
-    user: web::ReqData<String>,
+    claims: web::ReqData<Device>,
+    page: web::Query<PageQuery>,
 ) -> impl Responder {
+    let spec = match PageSpec::from_query(&page, DEVICE_SORTS, "registered") {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().json(format!("❌ {}", e)),
+    };
+
     let db = client.database("valutx");
-    let user_id = user.into_inner().clone();
-    let collection: Collection<mongodb::bson::Document> = db.collection("devices");
+    match find_page(&get_devices_collection(&db), doc! { "user_id": &claims.user_id }, &spec).await {
+        Ok(page) => {
+            let devices: Vec<_> = page
+                .items
+                .into_iter()
+                .map(|device| {
+                    json!({
+                        "device_id": device.device_id,
+                        "name": device.name,
+                        "status": device.status,
+                        "fingerprint": device.fingerprint.as_ref().map(fingerprint_summary),
+                        "approved_at": format_date(device.approved_at),
+                        "last_seen_at": format_date(device.last_seen_at),
+                        "current": device.device_id == claims.device_id,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({ "items": devices, "next_cursor": page.next_cursor }))
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch devices")
+        }
+    }
+}
+
+/// Sets a device's friendly name.
+#[patch("/devices/{device_id}")]
+pub async fn rename_device(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+    req: web::Json<DeviceRenameRequest>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+    let name = match device_name(&req.name) {
+        Some(name) => name,
+        None => {
+            return HttpResponse::BadRequest().json(format!(
+                "❌ Device name must be 1 to {} characters",
+                DEVICE_NAME_MAX_LEN
+            ));
+        }
+    };
 
-    match collection
+    let db = client.database("valutx");
+    match get_devices_collection(&db)
         .update_one(
-            doc! { "user_id": user_id },
-            doc! { "$set": { "new_device_flag": true } },
-            None,
+            doc! { "user_id": &claims.user_id, "device_id": &device_id },
+            doc! { "$set": { "name": name } },
         )
         .await
     {
-        Ok(_) => HttpResponse::Ok().json("Device approval flag enabled"),
+        Ok(result) if result.matched_count == 0 => {
+            HttpResponse::NotFound().json("❌ Device not found")
+        }
+        Ok(_) => HttpResponse::Ok().json("✅ Device renamed"),
         Err(e) => {
-            eprintln!("Failed to update device approval flag: {:?}", e);
-            HttpResponse::InternalServerError().json("Failed to update device approval flag")
+            error!("Failed to rename device: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
         }
     }
 }
 
-#[post("/devices/approve")]
-pub async fn approve_device(
+/// **Revokes one of the caller's devices**
+/// The device record is removed, so its tokens stop passing the auth
+/// middleware, and all of its sessions are ended.
+#[delete("/devices/{device_id}")]
+pub async fn revoke_device(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+    let db = client.database("valutx");
+
+    match get_devices_collection(&db)
+        .delete_one(doc! { "user_id": &claims.user_id, "device_id": &device_id })
+        .await
+    {
+        Ok(result) if result.deleted_count == 0 => {
+            return HttpResponse::NotFound().json("❌ Device not found");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to revoke device: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
+
+    let revoked = match revoke_sessions(
+        &client,
+        doc! { "user_id": &claims.user_id, "device_id": &device_id },
+    )
+    .await
+    {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Device revocation error: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    publish(&claims.user_id, VaultEvent::DeviceRevoked { device_id: device_id.clone() });
+    log_event(
+        &client,
+        &claims.user_id,
+        "device_revoked",
+        &format!(
+            "Device {} revoked from device {}; {} session(s) ended",
+            device_id, claims.device_id, revoked
+        ),
+    )
+    .await;
+
+    HttpResponse::Ok().json(json!({ "revoked_sessions": revoked }))
+}
+
+/// **Starts pairing a new device from a trusted one**
+/// Returns a single-use code, also packed into a QR payload, that the new device
+/// submits within `PAIRING_TTL_SECS`. Any earlier unfinished pairing is cancelled.
+#[post("/devices/pairing")]
+pub async fn start_pairing(client: web::Data<Client>, claims: web::ReqData<Device>) -> impl Responder {
+    let db = client.database("valutx");
+    let user = match get_users_collection(&db)
+        .find_one(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::Unauthorized().json("❌ Unknown user"),
+        Err(e) => {
+            error!("Failed to load user: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let pairings = get_device_pairings_collection(&db);
+    if let Err(e) = pairings
+        .delete_many(doc! { "user_id": &claims.user_id, "status": "open" })
+        .await
+    {
+        error!("Failed to cancel previous pairings: {}", e);
+        return HttpResponse::InternalServerError().json("❌ Internal server error");
+    }
+
+    let code = generate_pairing_code();
+    let now = DateTime::now();
+    let pairing = DevicePairing {
+        pairing_id: Uuid::new_v4().to_string(),
+        user_id: claims.user_id.clone(),
+        code_hash: hash_token(&normalize_code(&code)),
+        created_by: claims.device_id.clone(),
+        status: PairingStatus::Open,
+        attempts: 0,
+        created_at: now,
+        expires_at: DateTime::from_millis(now.timestamp_millis() + PAIRING_TTL_SECS * 1000),
+    };
+    if let Err(e) = pairings.insert_one(&pairing).await {
+        error!("Failed to store pairing: {}", e);
+        return HttpResponse::InternalServerError().json("❌ Internal server error");
+    }
+
+    let qr_payload = match Url::parse_with_params(
+        "valutx://pair",
+        &[("username", user.username.as_str()), ("code", code.as_str())],
+    ) {
+        Ok(url) => url.to_string(),
+        Err(e) => {
+            error!("Failed to build pairing payload: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    log_event(
+        &client,
+        &claims.user_id,
+        "pairing_started",
+        &format!("Pairing {} started from device {}", pairing.pairing_id, claims.device_id),
+    )
+    .await;
+
+    HttpResponse::Ok().json(json!({
+        "pairing_id": pairing.pairing_id,
+        "code": code,
+        "qr_payload": qr_payload,
+        "expires_at": format_date(Some(pairing.expires_at)),
+    }))
+}
+
+/// Matches a user's pairing that can still take a code submission: open, not
+/// expired and under `PAIRING_MAX_ATTEMPTS`.
+fn claimable_pairing(user_id: &str, now: DateTime) -> Document {
+    doc! {
+        "user_id": user_id,
+        "status": "open",
+        "attempts": { "$lt": PAIRING_MAX_ATTEMPTS },
+        "expires_at": { "$gt": now },
+    }
+}
+
+/// Whether a submitted code is the one issued for `pairing`, however it was typed.
+fn pairing_code_matches(pairing: &DevicePairing, code: &str) -> bool {
+    hash_token(&normalize_code(code)) == pairing.code_hash
+}
+
+/// Update that (re)queues a device for approval until `expires_at`. An expired
+/// device loses its old approval and last-seen time.
+fn pending_registration(fingerprint: Option<Bson>, now: DateTime, expires_at: DateTime) -> Document {
+    doc! {
+        "$set": {
+            "status": "pending",
+            "fingerprint": fingerprint,
+            "requested_at": now,
+            "pending_expires_at": expires_at,
+            "approved_at": null,
+            "last_seen_at": null,
+        }
+    }
+}
+
+/// **Registers a new device as pending approval**
+/// Called by the new device, which has no session yet, with a pairing code from
+/// a trusted device. Every submission counts against `PAIRING_MAX_ATTEMPTS`, so
+/// a code can't be guessed. The device is stored with the fingerprint it
+/// registered from and discarded if not approved within the pending timeout.
+#[post("/devices/register")]
+pub async fn register_device(
+    http_req: HttpRequest,
     client: web::Data<Client>,
-    body: web::Json<Device>,
-    user: web::ReqData<String>,
+    config: web::Data<Config>,
+    req: web::Json<DeviceRegistrationRequest>,
 ) -> impl Responder {
     let db = client.database("valutx");
-    let user_id = user.into_inner();
-    let collection: Collection<mongodb::bson::Document> = db.collection("devices");
+    let user = match get_users_collection(&db)
+        .find_one(doc! { "username": &req.username })
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::Unauthorized().json("❌ Invalid pairing code"),
+        Err(e) => {
+            error!("Failed to load user: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    // Take an attempt before checking the code so concurrent guesses can't exceed the limit
+    let pairings = get_device_pairings_collection(&db);
+    let pairing = match pairings
+        .find_one_and_update(
+            claimable_pairing(&user.user_id, DateTime::now()),
+            doc! { "$inc": { "attempts": 1 } },
+        )
+        .return_document(ReturnDocument::After)
+        .await
+    {
+        Ok(Some(pairing)) => pairing,
+        Ok(None) => return HttpResponse::Unauthorized().json("❌ Invalid pairing code"),
+        Err(e) => {
+            error!("Failed to load pairing: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    if !pairing_code_matches(&pairing, &req.pairing_code) {
+        log_event(
+            &client,
+            &user.user_id,
+            "pairing_failed",
+            &format!(
+                "Wrong code for pairing {} from device {} (attempt {} of {})",
+                pairing.pairing_id, req.device_id, pairing.attempts, PAIRING_MAX_ATTEMPTS
+            ),
+        )
+        .await;
+        return HttpResponse::Unauthorized().json("❌ Invalid pairing code");
+    }
+
+    let fingerprint = match to_stored(&observe(&http_req, &config), &config)
+        .map(|fingerprint| mongodb::bson::to_bson(&fingerprint))
+        .transpose()
+    {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            error!("Failed to encode fingerprint: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    // Spend the code; it is handed back if the device can't be stored
+    match pairings
+        .update_one(
+            doc! { "pairing_id": &pairing.pairing_id, "status": "open" },
+            doc! { "$set": { "status": "claimed" } },
+        )
+        .await
+    {
+        Ok(result) if result.modified_count == 1 => {}
+        Ok(_) => return HttpResponse::Conflict().json("❌ Pairing code already used"),
+        Err(e) => {
+            error!("Failed to claim pairing: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
 
-    // Look for an approval flag for the device for the given user.
-    match collection
-        .find_one(doc! { "user_id": &user_id, "new_device_flag": true }, None)
+    // Expired devices re-register in place; an approved device makes the upsert a duplicate
+    let now = DateTime::now();
+    let pending_expires_at =
+        DateTime::from_millis(now.timestamp_millis() + config.device_pending_secs * 1000);
+    match get_devices_collection(&db)
+        .update_one(
+            doc! {
+                "user_id": &user.user_id,
+                "device_id": &req.device_id,
+                "status": { "$ne": "approved" },
+            },
+            pending_registration(fingerprint, now, pending_expires_at),
+        )
+        .upsert(true)
         .await
     {
-        Ok(Some(_)) => {
-            // Insert approved device document.
-            match collection
-                .insert_one(
-                    doc! {
-                        "user_id": &user_id,
-                        "device_id": &body.device_id,
-                        "approved": true
-                    },
-                    None,
+        Ok(_) => {}
+        Err(e) => {
+            if let Err(e) = pairings
+                .update_one(
+                    doc! { "pairing_id": &pairing.pairing_id, "status": "claimed" },
+                    doc! { "$set": { "status": "open" } },
                 )
                 .await
             {
-                Ok(_) => HttpResponse::Ok().json("Device approved successfully"),
-                Err(e) => {
-                    eprintln!("Failed to approve device: {:?}", e);
-                    HttpResponse::InternalServerError().json("Failed to approve device")
-                }
+                error!("Failed to release pairing: {}", e);
+            }
+            if is_duplicate_key_error(&e) {
+                return HttpResponse::Conflict().json("❌ Device already approved");
             }
+            error!("Failed to register device: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
+
+    publish(&user.user_id, VaultEvent::DevicePending { device_id: req.device_id.clone() });
+    log_event(
+        &client,
+        &user.user_id,
+        "device_registered",
+        &format!(
+            "Device {} registered with pairing {}; awaiting approval",
+            req.device_id, pairing.pairing_id
+        ),
+    )
+    .await;
+
+    HttpResponse::Accepted().json(json!({
+        "device_id": req.device_id,
+        "expires_at": format_date(Some(pending_expires_at)),
+        "message": "✅ Waiting for approval on a trusted device",
+    }))
+}
+
+/// Lists devices waiting for approval, so a trusted device can check their fingerprints.
+#[get("/devices/pending")]
+pub async fn list_pending_devices(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    let cursor = match get_devices_collection(&db)
+        .find(doc! {
+            "user_id": &claims.user_id,
+            "status": "pending",
+            "pending_expires_at": { "$gt": DateTime::now() },
+        })
+        .await
+    {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            error!("Failed to fetch pending devices: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to fetch pending devices");
+        }
+    };
+
+    match cursor.try_collect::<Vec<_>>().await {
+        Ok(devices) => {
+            let devices: Vec<_> = devices
+                .into_iter()
+                .map(|device| {
+                    json!({
+                        "device_id": device.device_id,
+                        "fingerprint": device.fingerprint.as_ref().map(fingerprint_summary),
+                        "requested_at": format_date(device.requested_at),
+                        "expires_at": format_date(device.pending_expires_at),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(devices)
         }
-        Ok(None) => HttpResponse::Unauthorized().json("Device approval not enabled"),
         Err(e) => {
-            eprintln!("Database error: {:?}", e);
-            HttpResponse::InternalServerError().json("Database error")
+            error!("Failed to collect pending devices: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch pending devices")
         }
     }
 }
 
-#[post("/devices/register")]
-pub async fn register_device(req: web::Json<Device>) -> impl Responder {
-    // Now the fields are used, so the warning will disappear.
-    println!("Registering device '{}' for user '{}'", req.device_id, req.user_id);
-    HttpResponse::Ok().json("Device registered successfully")
+/// **Approves a pending device**
+/// The device is trusted with the fingerprint it registered from.
+#[post("/devices/{device_id}/approve")]
+pub async fn approve_device(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+    let db = client.database("valutx");
+    match get_devices_collection(&db)
+        .update_one(
+            doc! {
+                "user_id": &claims.user_id,
+                "device_id": &device_id,
+                "status": "pending",
+                "pending_expires_at": { "$gt": DateTime::now() },
+            },
+            doc! {
+                "$set": { "status": "approved", "approved_at": DateTime::now() },
+                "$unset": { "pending_expires_at": "" },
+            },
+        )
+        .await
+    {
+        Ok(result) if result.matched_count == 0 => {
+            return HttpResponse::NotFound().json("❌ No pending device to approve");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to approve device: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
+
+    publish(&claims.user_id, VaultEvent::DeviceApproved { device_id: device_id.clone() });
+    log_event(
+        &client,
+        &claims.user_id,
+        "device_approved",
+        &format!("Device {} approved from device {}", device_id, claims.device_id),
+    )
+    .await;
+
+    HttpResponse::Ok().json("✅ Device approved")
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs_ago(now: DateTime, secs: i64) -> DateTime {
+        DateTime::from_millis(now.timestamp_millis() - secs * 1000)
+    }
+
+    fn device(status: DeviceStatus) -> TrustedDevice {
+        TrustedDevice {
+            user_id: "u1".to_string(),
+            device_id: "d1".to_string(),
+            name: None,
+            status,
+            fingerprint: None,
+            approved_at: None,
+            last_seen_at: None,
+            requested_at: None,
+            pending_expires_at: None,
+            last_mismatch: None,
+        }
+    }
+
+    #[test]
+    fn test_idle_window() {
+        let now = DateTime::now();
+        let mut trusted = device(DeviceStatus::Approved);
+        assert!(!is_idle(&trusted, 60, now));
+
+        // Never used: counts from approval
+        trusted.approved_at = Some(secs_ago(now, 120));
+        assert!(is_idle(&trusted, 60, now));
+
+        // Recent use resets the window
+        trusted.last_seen_at = Some(secs_ago(now, 30));
+        assert!(!is_idle(&trusted, 60, now));
+        trusted.last_seen_at = Some(secs_ago(now, 61));
+        assert!(is_idle(&trusted, 60, now));
+    }
+
+    #[test]
+    fn test_device_name() {
+        assert_eq!(device_name("  Work laptop "), Some("Work laptop"));
+        assert_eq!(device_name("   "), None);
+        // The limit counts characters, not bytes
+        let longest = "é".repeat(DEVICE_NAME_MAX_LEN);
+        assert_eq!(device_name(&longest), Some(longest.as_str()));
+        assert_eq!(device_name(&"x".repeat(DEVICE_NAME_MAX_LEN + 1)), None);
+    }
+
+    #[test]
+    fn test_fingerprint_summary() {
+        let summary = fingerprint_summary(&DeviceFingerprint {
+            hardware_hash: Some("0123456789abcdef0123".to_string()),
+            ip_network: Some("203.0.113.0/24".to_string()),
+            tls_fingerprint: None,
+        });
+        assert_eq!(
+            summary,
+            json!({ "hardware": "0123456789ab", "ip_network": "203.0.113.0/24", "tls_bound": false })
+        );
+    }
+
+    #[test]
+    fn test_pairing_limits() {
+        let now = DateTime::now();
+        let filter = claimable_pairing("u1", now);
+        assert_eq!(filter.get_str("status"), Ok("open"));
+        assert_eq!(
+            filter.get_document("attempts").unwrap(),
+            &doc! { "$lt": PAIRING_MAX_ATTEMPTS }
+        );
+        assert_eq!(filter.get_document("expires_at").unwrap(), &doc! { "$gt": now });
+
+        let code = generate_pairing_code();
+        let pairing = DevicePairing {
+            pairing_id: "p1".to_string(),
+            user_id: "u1".to_string(),
+            code_hash: hash_token(&normalize_code(&code)),
+            created_by: "d1".to_string(),
+            status: PairingStatus::Open,
+            attempts: 0,
+            created_at: now,
+            expires_at: DateTime::from_millis(now.timestamp_millis() + PAIRING_TTL_SECS * 1000),
+        };
+        assert!(pairing_code_matches(&pairing, &code.to_lowercase()));
+        assert!(pairing_code_matches(&pairing, &code.replace('-', "")));
+        assert!(!pairing_code_matches(&pairing, "AAAA-BBBB"));
+    }
+
+    #[test]
+    fn test_pending_registration() {
+        let now = DateTime::now();
+        let expires_at = DateTime::from_millis(now.timestamp_millis() + 600 * 1000);
+        let update = pending_registration(None, now, expires_at);
+
+        // Round-trip through the model, as an expired device re-registering would read back
+        let mut expired = device(DeviceStatus::Expired);
+        expired.approved_at = Some(secs_ago(now, 3600));
+        expired.last_seen_at = Some(secs_ago(now, 1800));
+        let mut stored = mongodb::bson::to_document(&expired).unwrap();
+        stored.extend(update.get_document("$set").unwrap().clone());
+        let pending: TrustedDevice = mongodb::bson::from_document(stored).unwrap();
+        assert_eq!(pending.status, DeviceStatus::Pending);
+        assert_eq!(pending.requested_at, Some(now));
+        assert_eq!(pending.pending_expires_at, Some(expires_at));
+        assert!(pending.approved_at.is_none() && pending.last_seen_at.is_none());
+    }
+}
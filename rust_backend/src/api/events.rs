@@ -0,0 +1,146 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use futures::stream;
+use log::error;
+use mongodb::{bson::doc, Client};
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::{interval, sleep, Interval, MissedTickBehavior, Sleep};
+use crate::api::devices::{check_device_access, DeviceAccess};
+use crate::config::config::Config;
+use crate::db::collections::get_sessions_collection;
+use crate::models::device::Device;
+use crate::models::event::VaultEvent;
+use crate::utils::events::subscribe;
+
+/// Comment frames sent this often keep proxies from closing an idle stream.
+/// The session and device are re-checked at the same pace.
+const KEEPALIVE_SECS: u64 = 15;
+
+/// One open event stream
+struct Subscription {
+    events: Receiver<VaultEvent>,
+    keepalive: Interval,
+    /// Fires when the access token that opened the stream expires
+    expiry: Pin<Box<Sleep>>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: Device,
+    closed: bool,
+}
+
+/// Formats an event as a Server-Sent Events frame
+fn sse_frame(event: &VaultEvent) -> web::Bytes {
+    let data = serde_json::to_string(event).unwrap_or_else(|e| {
+        error!("Failed to encode event: {}", e);
+        "{}".to_string()
+    });
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event.name(), data))
+}
+
+/// Time left before a token with expiry `exp` (seconds since the epoch) runs out
+fn time_left(exp: usize) -> Duration {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Duration::from_secs(exp as u64).saturating_sub(now)
+}
+
+/// Whether the stream's session is still live and its device still approved
+async fn still_authorized(client: &Client, config: &Config, claims: &Device) -> Result<bool, String> {
+    let db = client.database("valutx");
+    let live = get_sessions_collection(&db)
+        .count_documents(doc! { "jti": &claims.jti, "revoked": false })
+        .await
+        .map_err(|e| format!("Failed to check session: {}", e))?;
+    if live == 0 {
+        return Ok(false);
+    }
+    check_device_access(client, config, &claims.user_id, &claims.device_id)
+        .await
+        .map(|access| matches!(access, DeviceAccess::Active(_)))
+}
+
+/// Waits for the next frame for this device, or `None` once the stream should end.
+async fn next_frame(sub: &mut Subscription) -> Option<web::Bytes> {
+    if sub.closed {
+        return None;
+    }
+    tokio::select! {
+        // The client reconnects with a fresh token
+        _ = &mut sub.expiry => None,
+        _ = sub.keepalive.tick() => {
+            match still_authorized(&sub.client, &sub.config, &sub.claims).await {
+                Ok(true) => Some(web::Bytes::from_static(b": keepalive\n\n")),
+                Ok(false) => None,
+                Err(e) => {
+                    error!("{}", e);
+                    None
+                }
+            }
+        }
+        received = sub.events.recv() => match received {
+            Ok(event) => {
+                // A revoked device gets the notice, then loses the stream
+                if let VaultEvent::DeviceRevoked { device_id } = &event {
+                    sub.closed = *device_id == sub.claims.device_id;
+                }
+                Some(sse_frame(&event))
+            }
+            Err(RecvError::Lagged(_)) => Some(sse_frame(&VaultEvent::Resync)),
+            Err(RecvError::Closed) => None,
+        },
+    }
+}
+
+/// **Streams the caller's vault, device and security events as Server-Sent Events**
+/// Events only say what changed; record contents still come from delta sync.
+/// The stream ends when the access token expires, or once the session is
+/// revoked or the device loses its approval.
+#[get("/events")]
+async fn events(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+) -> impl Responder {
+    let claims = claims.into_inner();
+    let mut keepalive = interval(Duration::from_secs(KEEPALIVE_SECS));
+    keepalive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick fires at once and the request was just authorized
+    keepalive.reset();
+    let subscription = Subscription {
+        events: subscribe(&claims.user_id),
+        keepalive,
+        expiry: Box::pin(sleep(time_left(claims.expiration))),
+        client,
+        config,
+        claims,
+        closed: false,
+    };
+
+    let frames = stream::unfold(subscription, |mut sub| async move {
+        next_frame(&mut sub)
+            .await
+            .map(|frame| (Ok::<_, actix_web::Error>(frame), sub))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_frame() {
+        let frame = sse_frame(&VaultEvent::VaultChanged { revision: 7, device_id: None });
+        assert_eq!(
+            frame,
+            web::Bytes::from_static(
+                b"event: vault_changed\ndata: {\"type\":\"vault_changed\",\"revision\":7,\"device_id\":null}\n\n"
+            )
+        );
+    }
+}
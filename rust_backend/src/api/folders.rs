@@ -0,0 +1,229 @@
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use futures::TryStreamExt;
+use log::error;
+use mongodb::{
+    bson::{doc, DateTime},
+    Client,
+};
+use serde_json::json;
+use uuid::Uuid;
+use crate::api::sync::next_revision;
+use crate::db::collections::{get_folders_collection, get_records_collection};
+use crate::models::device::Device;
+use crate::models::folder::{Folder, FolderDeleteQuery, LabelRequest};
+use crate::utils::logger::log_event;
+
+fn folder_json(folder: &Folder) -> serde_json::Value {
+    json!({
+        "folder_id": folder.folder_id,
+        "encrypted_name": folder.encrypted_name,
+        "created_at": folder.created_at.try_to_rfc3339_string().unwrap_or_default(),
+    })
+}
+
+/// Lists the caller's folders.
+#[get("")]
+async fn list_folders(client: web::Data<Client>, claims: web::ReqData<Device>) -> impl Responder {
+    let db = client.database("valutx");
+    let cursor = match get_folders_collection(&db)
+        .find(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            error!("Failed to fetch folders: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to fetch folders");
+        }
+    };
+
+    match cursor.try_collect::<Vec<_>>().await {
+        Ok(folders) => HttpResponse::Ok().json(folders.iter().map(folder_json).collect::<Vec<_>>()),
+        Err(e) => {
+            error!("Failed to collect folders: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch folders")
+        }
+    }
+}
+
+/// Creates a folder.
+#[post("")]
+async fn create_folder(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    req: web::Json<LabelRequest>,
+) -> impl Responder {
+    if req.encrypted_name.trim().is_empty() {
+        return HttpResponse::BadRequest().json("❌ Folder name cannot be empty");
+    }
+
+    let folder = Folder {
+        folder_id: Uuid::new_v4().to_string(),
+        user_id: claims.user_id.clone(),
+        encrypted_name: req.into_inner().encrypted_name,
+        created_at: DateTime::now(),
+    };
+    let db = client.database("valutx");
+    match get_folders_collection(&db).insert_one(&folder).await {
+        Ok(_) => HttpResponse::Created().json(folder_json(&folder)),
+        Err(e) => {
+            error!("Failed to create folder: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to create folder")
+        }
+    }
+}
+
+/// Renames a folder.
+#[put("/{folder_id}")]
+async fn rename_folder(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+    req: web::Json<LabelRequest>,
+) -> impl Responder {
+    if req.encrypted_name.trim().is_empty() {
+        return HttpResponse::BadRequest().json("❌ Folder name cannot be empty");
+    }
+
+    let db = client.database("valutx");
+    match get_folders_collection(&db)
+        .update_one(
+            doc! { "folder_id": path.into_inner(), "user_id": &claims.user_id },
+            doc! { "$set": { "encrypted_name": &req.encrypted_name } },
+        )
+        .await
+    {
+        Ok(result) if result.matched_count == 0 => {
+            HttpResponse::NotFound().json("❌ Folder not found")
+        }
+        Ok(_) => HttpResponse::Ok().json("✅ Folder renamed"),
+        Err(e) => {
+            error!("Failed to rename folder: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to rename folder")
+        }
+    }
+}
+
+/// **Deletes a folder**
+/// Its records are left unfiled, or moved to the trash with `?trash_records=true`.
+/// Either way no record keeps pointing at the deleted folder.
+#[delete("/{folder_id}")]
+async fn delete_folder(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+    query: web::Query<FolderDeleteQuery>,
+) -> impl Responder {
+    let folder_id = path.into_inner();
+    let db = client.database("valutx");
+    let folders = get_folders_collection(&db);
+    let folder_filter = doc! { "folder_id": &folder_id, "user_id": &claims.user_id };
+    match folders.find_one(folder_filter.clone()).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json("❌ Folder not found"),
+        Err(e) => {
+            error!("Failed to fetch folder: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to delete folder");
+        }
+    }
+
+    // Records are updated before the folder goes, so a failure part way leaves the
+    // folder in place for a retry. One revision covers every record touched.
+    let lease = match next_revision(&client, &claims.user_id).await {
+        Ok(lease) => lease,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to delete folder");
+        }
+    };
+    let revision = lease.revision();
+    let records = get_records_collection(&db);
+    let filter = doc! { "user_id": &claims.user_id, "folder_id": &folder_id };
+    let trashed_ids: Vec<String> = if query.trash_records {
+        let mut live = filter.clone();
+        live.insert("deleted_at", mongodb::bson::Bson::Null);
+        let ids: Vec<String> = match records.find(live).await {
+            Ok(cursor) => match cursor.map_ok(|record| record.id).try_collect().await {
+                Ok(ids) => ids,
+                Err(e) => {
+                    error!("Failed to collect folder records: {}", e);
+                    return HttpResponse::InternalServerError().json("❌ Failed to delete folder");
+                }
+            },
+            Err(e) => {
+                error!("Failed to fetch folder records: {}", e);
+                return HttpResponse::InternalServerError().json("❌ Failed to delete folder");
+            }
+        };
+        if !ids.is_empty() {
+            if let Err(e) = records
+                .update_many(
+                    doc! {
+                        "_id": { "$in": &ids },
+                        "user_id": &claims.user_id,
+                        "deleted_at": null,
+                    },
+                    doc! {
+                        "$set": { "deleted_at": DateTime::now(), "revision": revision },
+                        "$inc": { "version": 1 },
+                    },
+                )
+                .await
+            {
+                error!("Failed to trash folder records: {}", e);
+                return HttpResponse::InternalServerError().json("❌ Failed to delete folder");
+            }
+        }
+        ids
+    } else {
+        Vec::new()
+    };
+    let trashed = trashed_ids.len();
+    let unfiled = match records
+        .update_many(
+            filter,
+            doc! { "$set": { "folder_id": null, "revision": revision }, "$inc": { "version": 1 } },
+        )
+        .await
+    {
+        Ok(result) => result.modified_count,
+        Err(e) => {
+            error!("Failed to unfile folder records: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to delete folder");
+        }
+    };
+
+    if let Err(e) = folders.delete_one(folder_filter).await {
+        error!("Failed to delete folder: {}", e);
+        return HttpResponse::InternalServerError().json("❌ Failed to delete folder");
+    }
+
+    if unfiled > 0 {
+        lease.commit(Some(&claims.device_id)).await;
+    }
+    if !trashed_ids.is_empty() {
+        log_event(
+            &client,
+            &claims.user_id,
+            "record_trashed",
+            &format!(
+                "Records {} moved to trash with folder {} from device {}",
+                trashed_ids.join(", "),
+                folder_id,
+                claims.device_id
+            ),
+        )
+        .await;
+    }
+    log_event(
+        &client,
+        &claims.user_id,
+        "folder_deleted",
+        &format!(
+            "Folder {} deleted from device {}; {} record(s) unfiled, {} moved to trash",
+            folder_id, claims.device_id, unfiled, trashed
+        ),
+    )
+    .await;
+
+    HttpResponse::Ok().json(json!({ "unfiled": unfiled, "trashed": trashed }))
+}
@@ -0,0 +1,72 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use log::error;
+use mongodb::{
+    bson::{doc, to_bson},
+    Client, Database,
+};
+use crate::config::config::Config;
+use crate::db::collections::get_users_collection;
+use crate::models::key::KdfRequest;
+use crate::utils::kdf::{decoy_kdf_params, load_kdf_policies, new_kdf_params};
+
+async fn decoy_response(db: &Database, config: &Config, username: &str) -> HttpResponse {
+    match load_kdf_policies(db).await {
+        Ok(policies) => HttpResponse::Ok().json(decoy_kdf_params(config, &policies, username)),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
+
+/// **Returns the Argon2id parameters for deriving a user's master key**
+/// The master key comes from the master password, which is separate from the
+/// account password sent to `/login` and never reaches the server. Open to
+/// callers without a session, so unknown usernames get stable decoy parameters
+/// instead of an error that would reveal which accounts exist.
+#[post("/kdf")]
+pub async fn kdf_params(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    req: web::Json<KdfRequest>,
+) -> impl Responder {
+    let username = req.username.trim();
+    let db = client.database("valutx");
+    let users = get_users_collection(&db);
+    let user = match users.find_one(doc! { "username": username }).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return decoy_response(&db, &config, username).await,
+        Err(e) => {
+            error!("Failed to load user: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+    if let Some(kdf) = user.kdf {
+        return HttpResponse::Ok().json(kdf);
+    }
+
+    // Accounts from before KDF parameters were stored get them now; the first caller wins
+    let kdf = new_kdf_params(&config);
+    let stored = match to_bson(&kdf) {
+        Ok(stored) => stored,
+        Err(e) => {
+            error!("Failed to encode KDF parameters: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+    if let Err(e) = users
+        .update_one(doc! { "user_id": &user.user_id, "kdf": null }, doc! { "$set": { "kdf": stored } })
+        .await
+    {
+        error!("Failed to store KDF parameters: {}", e);
+        return HttpResponse::InternalServerError().json("❌ Internal server error");
+    }
+    match users.find_one(doc! { "user_id": &user.user_id }).await {
+        Ok(Some(user)) => HttpResponse::Ok().json(user.kdf.unwrap_or(kdf)),
+        Ok(None) => decoy_response(&db, &config, username).await,
+        Err(e) => {
+            error!("Failed to load user: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use actix_web::{get, post, put, web, HttpResponse, Responder};
+use log::error;
+use mongodb::{
+    bson::{doc, to_bson, DateTime},
+    Client,
+};
+use serde_json::json;
+use crate::db::collections::{get_vault_keys_collection, is_duplicate_key_error};
+use crate::models::device::Device;
+use crate::models::key::{VaultKey, VaultKeyRequest};
+use crate::utils::logger::log_event;
+
+fn vault_key_json(key: &VaultKey) -> serde_json::Value {
+    json!({
+        "wrapped_key": key.wrapped_key,
+        "key_version": key.key_version,
+        "created_at": key.created_at.try_to_rfc3339_string().unwrap_or_default(),
+        "updated_at": key.updated_at.try_to_rfc3339_string().unwrap_or_default(),
+    })
+}
+
+/// Returns the caller's vault key, wrapped by their master key.
+#[get("")]
+async fn get_vault_key(client: web::Data<Client>, claims: web::ReqData<Device>) -> impl Responder {
+    let db = client.database("valutx");
+    match get_vault_keys_collection(&db)
+        .find_one(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(Some(key)) => HttpResponse::Ok().json(vault_key_json(&key)),
+        Ok(None) => HttpResponse::NotFound().json("❌ No vault key stored"),
+        Err(e) => {
+            error!("Failed to fetch vault key: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch vault key")
+        }
+    }
+}
+
+/// **Stores the caller's first wrapped vault key**
+/// The client generates the vault key and wraps it before upload. There is one per
+/// user; replacing it would orphan every item key, so later changes go through rewrap.
+#[post("")]
+async fn create_vault_key(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    req: web::Json<VaultKeyRequest>,
+) -> impl Responder {
+    let req = req.into_inner();
+    if let Err(e) = req.wrapped_key.validate() {
+        return HttpResponse::BadRequest().json(format!("❌ {}", e));
+    }
+
+    let now = DateTime::now();
+    let key = VaultKey {
+        user_id: claims.user_id.clone(),
+        wrapped_key: req.wrapped_key,
+        key_version: 1,
+        created_at: now,
+        updated_at: now,
+    };
+    let db = client.database("valutx");
+    match get_vault_keys_collection(&db).insert_one(&key).await {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key_error(&e) => {
+            return HttpResponse::Conflict().json("❌ Vault key already exists");
+        }
+        Err(e) => {
+            error!("Failed to store vault key: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to store vault key");
+        }
+    }
+
+    log_event(
+        &client,
+        &claims.user_id,
+        "vault_key_created",
+        &format!("Vault key stored from device {}", claims.device_id),
+    )
+    .await;
+
+    HttpResponse::Created().json(vault_key_json(&key))
+}
+
+/// **Replaces the wrapped vault key after a master password change**
+/// The vault key itself stays the same, so records and their item keys are untouched.
+/// `replaces_version` must name the current version; otherwise 409 returns the stored key.
+#[put("")]
+async fn rewrap_vault_key(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    req: web::Json<VaultKeyRequest>,
+) -> impl Responder {
+    let req = req.into_inner();
+    let replaces_version = match req.replaces_version {
+        Some(version) => version,
+        None => return HttpResponse::BadRequest().json("❌ replaces_version is required"),
+    };
+    if let Err(e) = req.wrapped_key.validate() {
+        return HttpResponse::BadRequest().json(format!("❌ {}", e));
+    }
+    let wrapped_key = match to_bson(&req.wrapped_key) {
+        Ok(wrapped_key) => wrapped_key,
+        Err(e) => {
+            error!("Failed to encode vault key: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to update vault key");
+        }
+    };
+
+    let db = client.database("valutx");
+    let collection = get_vault_keys_collection(&db);
+    match collection
+        .update_one(
+            doc! { "user_id": &claims.user_id, "key_version": replaces_version },
+            doc! {
+                "$set": { "wrapped_key": wrapped_key, "updated_at": DateTime::now() },
+                "$inc": { "key_version": 1_i64 },
+            },
+        )
+        .await
+    {
+        Ok(result) if result.matched_count == 0 => {
+            return match collection.find_one(doc! { "user_id": &claims.user_id }).await {
+                Ok(Some(current)) => HttpResponse::Conflict().json(json!({
+                    "error": "conflict",
+                    "message": "❌ Vault key was rewrapped by another device",
+                    "vault_key": vault_key_json(&current),
+                })),
+                Ok(None) => HttpResponse::NotFound().json("❌ No vault key stored"),
+                Err(e) => {
+                    error!("Failed to fetch vault key: {}", e);
+                    HttpResponse::InternalServerError().json("❌ Failed to update vault key")
+                }
+            };
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to update vault key: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to update vault key");
+        }
+    }
+
+    log_event(
+        &client,
+        &claims.user_id,
+        "vault_key_rewrapped",
+        &format!(
+            "Vault key rewrapped from device {} (version {} replaced)",
+            claims.device_id, replaces_version
+        ),
+    )
+    .await;
+
+    HttpResponse::Ok().json(json!({ "key_version": replaces_version + 1 }))
+}
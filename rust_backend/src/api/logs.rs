@@ -1,30 +1,46 @@
 use actix_web::{get, web, HttpResponse, Responder};
-use mongodb::Client;
-use futures::stream::TryStreamExt; // Import for try_collect
-use std::env;
-use crate::models::log::LogEntry;
+use log::error;
+use mongodb::{bson::doc, Client};
+use serde_json::json;
+use crate::db::collections::{find_page, get_logs_collection, PageSpec};
+use crate::models::device::Device;
+use crate::models::pagination::PageQuery;
 
-#[get("/secure/logs")]
-async fn get_logs(client: web::Data<Client>) -> impl Responder {
-    // Use environment variable for database name or default to "valutx"
-    let db_name = env::var("MONGO_DB_NAME").unwrap_or_else(|_| "valutx".to_string());
-    let db = client.database(&db_name);
-    let collection = db.collection::<LogEntry>("logs");
+/// Sort keys accepted by the log listing; `_id` follows insertion time
+const LOG_SORTS: &[(&str, &str)] = &[("timestamp", "_id")];
 
-    // Fetch logs from the collection
-    match collection.find(None, None).await {
-        Ok(mut cursor) => {
-            let mut logs = Vec::new();
+/// Lists the caller's security log, newest first by default, a page at a time.
+#[get("/logs")]
+async fn get_logs(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    page: web::Query<PageQuery>,
+) -> impl Responder {
+    let spec = match PageSpec::from_query(&page, LOG_SORTS, "-timestamp") {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().json(format!("❌ {}", e)),
+    };
 
-            while let Some(result) = cursor.try_next().await.unwrap_or_else(|_| None) {
-                logs.push(result);
-            }
-
-            HttpResponse::Ok().json(logs)
+    let db = client.database("valutx");
+    match find_page(&get_logs_collection(&db), doc! { "user_id": &claims.user_id }, &spec).await {
+        Ok(page) => {
+            let logs: Vec<_> = page
+                .items
+                .into_iter()
+                .map(|entry| {
+                    json!({
+                        "id": entry.id.to_hex(),
+                        "timestamp": entry.timestamp,
+                        "event_type": entry.event_type,
+                        "details": entry.details,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({ "items": logs, "next_cursor": page.next_cursor }))
         }
         Err(e) => {
-            eprintln!("❌ Failed to fetch logs: {}", e);
-            HttpResponse::InternalServerError().json("Failed to fetch logs from the database")
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch logs")
         }
     }
 }
@@ -1,24 +1,101 @@
 use crate::middleware::auth_middleware::validate_request;
-use actix_web::web;
+use crate::middleware::totp_middleware::require_totp;
+use actix_web::{middleware::from_fn, web};
 use actix_web_httpauth::middleware::HttpAuthentication;
 
 pub(crate) mod authentication;
 mod backup;
-mod devices;
+pub(crate) mod devices;
+mod events;
+mod folders;
+mod kdf;
+mod keys;
 mod logs;
-mod records;
-mod registration;
+pub(crate) mod records;
+mod recovery;
+pub(crate) mod registration;
+pub(crate) mod sessions;
+pub(crate) mod sync;
+mod tags;
+pub(crate) mod tokens;
+pub(crate) mod totp;
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     let auth_middleware = HttpAuthentication::bearer(validate_request);
 
     cfg.service(registration::register)
        .service(authentication::login)
+       .service(authentication::start_passkey_login)
+       .service(authentication::finish_passkey_login)
+       .service(tokens::refresh)
+       .service(kdf::kdf_params)
+       .service(devices::register_device)
        .service(
            web::scope("/secure")
                .wrap(auth_middleware)
-               .service(records::get_records)
-               .service(devices::register_device)
-               .service(logs::get_logs),
+               .service(
+                   // Record sync requires a TOTP-verified session
+                   web::scope("/records")
+                       .wrap(from_fn(require_totp))
+                       .service(records::get_records)
+                       .service(records::get_trash)
+                       .service(records::get_record)
+                       .service(records::create_record)
+                       .service(records::update_record)
+                       .service(records::delete_record)
+                       .service(records::get_record_history)
+                       .service(records::restore_record_revision)
+                       .service(records::recover_record),
+               )
+               .service(
+                   web::scope("/backup")
+                       .app_data(web::JsonConfig::default().limit(backup::MAX_BACKUP_BYTES))
+                       .service(backup::export_backup)
+                       .service(backup::restore_backup),
+               )
+               .service(
+                   web::scope("/keys")
+                       .service(keys::get_vault_key)
+                       .service(keys::create_vault_key)
+                       .service(keys::rewrap_vault_key),
+               )
+               .service(
+                   web::scope("/sync")
+                       .wrap(from_fn(require_totp))
+                       .service(sync::sync),
+               )
+               .service(
+                   web::scope("/folders")
+                       .service(folders::list_folders)
+                       .service(folders::create_folder)
+                       .service(folders::rename_folder)
+                       .service(folders::delete_folder),
+               )
+               .service(
+                   web::scope("/tags")
+                       .service(tags::list_tags)
+                       .service(tags::create_tag)
+                       .service(tags::rename_tag)
+                       .service(tags::delete_tag),
+               )
+               .service(authentication::register_webauthn)
+               .service(authentication::verify_webauthn)
+               .service(devices::list_devices)
+               .service(devices::list_pending_devices)
+               .service(devices::start_pairing)
+               .service(devices::approve_device)
+               .service(devices::rename_device)
+               .service(devices::revoke_device)
+               .service(logs::get_logs)
+               .service(events::events)
+               .service(sessions::logout)
+               .service(sessions::list_sessions)
+               .service(sessions::revoke_session)
+               .service(sessions::revoke_all_sessions)
+               .service(totp::enroll_totp)
+               .service(totp::confirm_totp)
+               .service(recovery::regenerate_recovery_codes)
+               .service(recovery::recovery_codes_status)
+               .service(recovery::redeem_recovery_code),
        );
 }
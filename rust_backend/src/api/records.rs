@@ -1,31 +1,1053 @@
-use actix_web::{get, post, web, HttpResponse, Responder};
-use mongodb::Database;
-use crate::db::collections::insert_record;
-use crate::models::record::Record;
+use actix_web::{
+    delete, get,
+    http::{
+        header::{EntityTag, Header, IfMatch, ETag, IF_MATCH},
+        StatusCode,
+    },
+    post, put, web, HttpRequest, HttpResponse, Responder,
+};
+use futures::TryStreamExt;
+use log::error;
+use mongodb::{
+    bson::{doc, Bson, DateTime, Document},
+    Client,
+};
+use serde_json::json;
+use uuid::Uuid;
+use crate::config::config::Config;
+use crate::api::sync::next_revision;
+use crate::db::collections::{
+    find_page, get_folders_collection, get_record_revisions_collection,
+    get_record_tombstones_collection, get_records_collection, get_tags_collection,
+    insert_record, PageSpec,
+};
+use crate::models::device::Device;
+use crate::models::pagination::PageQuery;
+use crate::models::record::{Record, RecordQuery, RecordRequest, RecordRevision, RecordType};
+use crate::models::sync::RecordTombstone;
+use crate::utils::logger::log_event;
 
-#[get("/records")]
-async fn get_records() -> impl Responder {
-    let records = vec![
-        Record { id: "1".to_string(), title: "My First Record".to_string(), encrypted_data: "encrypted1".to_string() },
-        Record { id: "2".to_string(), title: "Secure Password".to_string(), encrypted_data: "encrypted2".to_string() },
-    ];
-    HttpResponse::Ok().json(records)
+fn format_date(date: DateTime) -> String {
+    date.try_to_rfc3339_string().unwrap_or_default()
 }
 
-#[post("/records")]
-async fn create_record(db: web::Data<Database>, record: web::Json<Record>) -> impl Responder {
-    let api_record = record.into_inner();
+/// Client-facing view of a record
+pub fn record_json(record: &Record) -> serde_json::Value {
+    json!({
+        "id": record.id,
+        "record_type": record.record_type,
+        "title": record.title,
+        "metadata": record.metadata,
+        "encrypted_data": record.encrypted_data,
+        "wrapped_item_key": record.wrapped_item_key,
+        "folder_id": record.folder_id,
+        "tag_ids": record.tag_ids,
+        "revision": record.revision,
+        "version": record.version,
+        "created_at": format_date(record.created_at),
+        "updated_at": format_date(record.updated_at),
+    })
+}
+
+/// Whether the record's folder and tags all exist and belong to its owner.
+async fn check_labels(client: &Client, record: &Record) -> Result<bool, String> {
+    let db = client.database("valutx");
+    if let Some(folder_id) = &record.folder_id {
+        let found = get_folders_collection(&db)
+            .count_documents(doc! { "folder_id": folder_id, "user_id": &record.user_id })
+            .await
+            .map_err(|e| format!("Failed to check folder: {}", e))?;
+        if found == 0 {
+            return Ok(false);
+        }
+    }
+    if !record.tag_ids.is_empty() {
+        let found = get_tags_collection(&db)
+            .count_documents(doc! {
+                "tag_id": { "$in": &record.tag_ids },
+                "user_id": &record.user_id,
+            })
+            .await
+            .map_err(|e| format!("Failed to check tags: {}", e))?;
+        if found != record.tag_ids.len() as u64 {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Filter value matching a record at `version`. Records stored before versions
+/// existed have no `version` field and read back as 0, so 0 matches those too.
+fn version_filter(version: i64) -> Bson {
+    if version == 0 {
+        Bson::Document(doc! { "$in": [0_i64, null] })
+    } else {
+        Bson::Int64(version)
+    }
+}
+
+/// Strong ETag for a record's current version
+fn record_etag(record: &Record) -> ETag {
+    ETag(EntityTag::new_strong(record.version.to_string()))
+}
+
+/// **Reads the record version the client expects from `If-Match`**
+/// Writes must name the version they were based on; `None` means `If-Match: *`.
+fn expected_version(req: &HttpRequest) -> Result<Option<i64>, (StatusCode, &'static str)> {
+    if !req.headers().contains_key(IF_MATCH) {
+        return Err((
+            StatusCode::PRECONDITION_REQUIRED,
+            "❌ If-Match header with the record's ETag is required",
+        ));
+    }
+    match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => Ok(None),
+        Ok(IfMatch::Items(tags)) => tags
+            .iter()
+            .find_map(|tag| tag.tag().parse::<i64>().ok())
+            .map(Some)
+            .ok_or((StatusCode::BAD_REQUEST, "❌ Invalid If-Match header")),
+        Err(_) => Err((StatusCode::BAD_REQUEST, "❌ Invalid If-Match header")),
+    }
+}
+
+/// 409 carrying the server's copy, so the client can merge and retry with its ETag.
+fn conflict_response(current: &Record) -> HttpResponse {
+    HttpResponse::Conflict()
+        .insert_header(record_etag(current))
+        .json(json!({
+            "error": "conflict",
+            "message": "❌ Record was changed by another device",
+            "record": record_json(current),
+        }))
+}
+
+/// **Appends the record's contents to its history**
+/// Called before the record itself is written, so a write never lands without
+/// its revision. Returns the new revision's id. Conflicting writes are stored
+/// with `conflict` set and the version they were based on.
+async fn save_revision(
+    client: &Client,
+    record: &Record,
+    device_id: &str,
+    conflict: bool,
+) -> Result<String, String> {
+    let db = client.database("valutx");
+    let revision = RecordRevision {
+        revision_id: Uuid::new_v4().to_string(),
+        record_id: record.id.clone(),
+        user_id: record.user_id.clone(),
+        record_type: record.record_type,
+        title: record.title.clone(),
+        metadata: record.metadata.clone(),
+        encrypted_data: record.encrypted_data.clone(),
+        wrapped_item_key: record.wrapped_item_key.clone(),
+        device_id: device_id.to_string(),
+        version: record.version,
+        conflict,
+        created_at: record.updated_at,
+    };
+    get_record_revisions_collection(&db)
+        .insert_one(&revision)
+        .await
+        .map_err(|e| format!("Failed to store record revision: {}", e))?;
+    Ok(revision.revision_id)
+}
+
+/// Drops a revision whose record write did not go through.
+async fn discard_revision(client: &Client, revision_id: &str) {
+    let db = client.database("valutx");
+    if let Err(e) = get_record_revisions_collection(&db)
+        .delete_one(doc! { "revision_id": revision_id })
+        .await
+    {
+        error!("Failed to discard record revision {}: {}", revision_id, e);
+    }
+}
+
+/// Picks the revisions past the history limit, given newest first. Edits and
+/// conflicts are capped separately so a run of rejected writes can't push a
+/// record's real history out.
+fn expired_revisions(revisions: &[RecordRevision], limit: usize) -> Vec<String> {
+    let (mut edits, mut conflicts) = (0, 0);
+    revisions
+        .iter()
+        .filter(|revision| {
+            let kept = if revision.conflict { &mut conflicts } else { &mut edits };
+            *kept += 1;
+            *kept > limit
+        })
+        .map(|revision| revision.revision_id.clone())
+        .collect()
+}
+
+/// Keeps only the newest `record_history_limit` edits and conflicts of a record.
+async fn prune_revisions(client: &Client, config: &Config, record_id: &str) -> Result<(), String> {
+    let db = client.database("valutx");
+    let revisions = get_record_revisions_collection(&db);
+    let history: Vec<RecordRevision> = revisions
+        .find(doc! { "record_id": record_id })
+        .sort(doc! { "created_at": -1 })
+        .await
+        .map_err(|e| format!("Failed to load record revisions: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to load record revisions: {}", e))?;
+    let expired = expired_revisions(&history, config.record_history_limit as usize);
+    if !expired.is_empty() {
+        revisions
+            .delete_many(doc! { "revision_id": { "$in": expired } })
+            .await
+            .map_err(|e| format!("Failed to prune record revisions: {}", e))?;
+    }
+    Ok(())
+}
+
+/// A new record owned by `user_id`, with a server-generated id, at version 1
+fn new_record(user_id: &str, req: RecordRequest, now: DateTime) -> Record {
+    Record {
+        id: Uuid::new_v4().to_string(),
+        user_id: user_id.to_string(),
+        record_type: req.record_type,
+        title: req.title,
+        metadata: req.metadata,
+        encrypted_data: req.encrypted_data,
+        wrapped_item_key: req.wrapped_item_key,
+        folder_id: req.folder_id,
+        tag_ids: req.tag_ids,
+        created_at: now,
+        updated_at: now,
+        deleted_at: None,
+        revision: 0,
+        created_revision: 0,
+        version: 1,
+    }
+}
+
+/// `current` with the client's edit applied. Id, owner and creation stay the
+/// server's; the version is the one the client based the edit on.
+fn edited_record(current: &Record, req: RecordRequest, expected: Option<i64>, now: DateTime) -> Record {
+    Record {
+        id: current.id.clone(),
+        user_id: current.user_id.clone(),
+        record_type: req.record_type,
+        title: req.title,
+        metadata: req.metadata,
+        encrypted_data: req.encrypted_data,
+        wrapped_item_key: req.wrapped_item_key,
+        folder_id: req.folder_id,
+        tag_ids: req.tag_ids,
+        created_at: current.created_at,
+        updated_at: now,
+        deleted_at: None,
+        revision: current.revision,
+        created_revision: current.created_revision,
+        version: expected.unwrap_or(current.version),
+    }
+}
+
+/// Sort keys accepted by the records listing
+const RECORD_SORTS: &[(&str, &str)] = &[
+    ("updated_at", "updated_at"),
+    ("created_at", "created_at"),
+    ("title", "title"),
+];
+
+/// Live records of `user_id` matching the listing's type, folder and tag filters
+fn listing_filter(user_id: &str, query: &RecordQuery) -> Document {
+    let mut filter = doc! { "user_id": user_id, "deleted_at": null };
+    match query.record_type {
+        // Records stored before types existed have no `record_type` and read back as notes
+        Some(RecordType::SecureNote) => {
+            filter.insert("record_type", doc! { "$in": [RecordType::SecureNote.as_str(), null] });
+        }
+        Some(record_type) => {
+            filter.insert("record_type", record_type.as_str());
+        }
+        None => {}
+    }
+    if let Some(folder_id) = &query.folder {
+        filter.insert("folder_id", folder_id);
+    }
+    // Matches any record whose `tag_ids` contains the tag
+    if let Some(tag_id) = &query.tag {
+        filter.insert("tag_ids", tag_id);
+    }
+    filter
+}
+
+/// **Lists the caller's records a page at a time**
+/// Filters: `type`, `folder` and `tag`; paging: `limit`, `after` and `sort`
+/// (default `-updated_at`).
+#[get("")]
+async fn get_records(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    query: web::Query<RecordQuery>,
+    page: web::Query<PageQuery>,
+) -> impl Responder {
+    let spec = match PageSpec::from_query(&page, RECORD_SORTS, "-updated_at") {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().json(format!("❌ {}", e)),
+    };
+
+    let db = client.database("valutx");
+    match find_page(&get_records_collection(&db), listing_filter(&claims.user_id, &query), &spec).await {
+        Ok(page) => HttpResponse::Ok().json(json!({
+            "items": page.items.iter().map(record_json).collect::<Vec<_>>(),
+            "next_cursor": page.next_cursor,
+        })),
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch records")
+        }
+    }
+}
+
+/// Returns one of the caller's records.
+#[get("/{record_id}")]
+async fn get_record(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    match get_records_collection(&db)
+        .find_one(doc! { "_id": path.into_inner(), "user_id": &claims.user_id, "deleted_at": null })
+        .await
+    {
+        Ok(Some(record)) => HttpResponse::Ok()
+            .insert_header(record_etag(&record))
+            .json(record_json(&record)),
+        Ok(None) => HttpResponse::NotFound().json("❌ Record not found"),
+        Err(e) => {
+            error!("Failed to fetch record: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch record")
+        }
+    }
+}
+
+/// Creates a record owned by the caller; the id is generated here.
+#[post("")]
+async fn create_record(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+    req: web::Json<RecordRequest>,
+) -> impl Responder {
+    let mut record = new_record(&claims.user_id, req.into_inner(), DateTime::now());
+    if let Err(e) = record.validate() {
+        return HttpResponse::BadRequest().json(format!("❌ {}", e));
+    }
+    match check_labels(&client, &record).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().json("❌ Unknown folder or tag"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to create record");
+        }
+    }
+    let lease = match next_revision(&client, &claims.user_id).await {
+        Ok(lease) => lease,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to create record");
+        }
+    };
+    record.revision = lease.revision();
+    record.created_revision = lease.revision();
+
+    let revision_id = match save_revision(&client, &record, &claims.device_id, false).await {
+        Ok(revision_id) => revision_id,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to create record");
+        }
+    };
+
+    match insert_record(&client.database("valutx"), &record).await {
+        Ok(_) => {
+            if let Err(e) = prune_revisions(&client, &config, &record.id).await {
+                error!("{}", e);
+            }
+            lease.commit(Some(&claims.device_id)).await;
+            HttpResponse::Created()
+                .insert_header(record_etag(&record))
+                .json(record_json(&record))
+        }
+        Err(_) => {
+            discard_revision(&client, &revision_id).await;
+            HttpResponse::InternalServerError().json("❌ Failed to create record")
+        }
+    }
+}
+
+/// **Replaces the contents of one of the caller's records**
+/// `If-Match` must carry the version the edit was based on. If the record has
+/// changed since, the edit is kept in history as a conflict and a 409 returns
+/// the server copy.
+#[put("/{record_id}")]
+async fn update_record(
+    http_req: HttpRequest,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+    req: web::Json<RecordRequest>,
+) -> impl Responder {
+    let expected = match expected_version(&http_req) {
+        Ok(expected) => expected,
+        Err((status, message)) => return HttpResponse::build(status).json(message),
+    };
+
+    let db = client.database("valutx");
+    let collection = get_records_collection(&db);
+    let filter = doc! { "_id": path.into_inner(), "user_id": &claims.user_id, "deleted_at": null };
+
+    let current = match collection.find_one(filter.clone()).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return HttpResponse::NotFound().json("❌ Record not found"),
+        Err(e) => {
+            error!("Failed to fetch record: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to update record");
+        }
+    };
+
+    let mut record = edited_record(&current, req.into_inner(), expected, DateTime::now());
+    if let Err(e) = record.validate() {
+        return HttpResponse::BadRequest().json(format!("❌ {}", e));
+    }
+    if record.version != current.version {
+        return reject_conflict(&client, &config, &current, &record, &claims.device_id).await;
+    }
+    match check_labels(&client, &record).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().json("❌ Unknown folder or tag"),
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to update record");
+        }
+    }
+    let lease = match next_revision(&client, &claims.user_id).await {
+        Ok(lease) => lease,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to update record");
+        }
+    };
+    record.revision = lease.revision();
+    record.version = current.version + 1;
+    let revision_id = match save_revision(&client, &record, &claims.device_id, false).await {
+        Ok(revision_id) => revision_id,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to update record");
+        }
+    };
+
+    // The version in the filter catches a write that landed since the read above
+    let mut versioned = filter.clone();
+    versioned.insert("version", version_filter(current.version));
+    match collection.replace_one(versioned, &record).await {
+        Ok(result) if result.matched_count == 0 => {
+            discard_revision(&client, &revision_id).await;
+            match collection.find_one(filter).await {
+                Ok(Some(latest)) => {
+                    record.version = current.version;
+                    reject_conflict(&client, &config, &latest, &record, &claims.device_id).await
+                }
+                Ok(None) => HttpResponse::NotFound().json("❌ Record not found"),
+                Err(e) => {
+                    error!("Failed to fetch record: {}", e);
+                    HttpResponse::InternalServerError().json("❌ Failed to update record")
+                }
+            }
+        }
+        Ok(_) => {
+            if let Err(e) = prune_revisions(&client, &config, &record.id).await {
+                error!("{}", e);
+            }
+            lease.commit(Some(&claims.device_id)).await;
+            HttpResponse::Ok()
+                .insert_header(record_etag(&record))
+                .json(record_json(&record))
+        }
+        Err(e) => {
+            error!("Failed to update record: {}", e);
+            discard_revision(&client, &revision_id).await;
+            HttpResponse::InternalServerError().json("❌ Failed to update record")
+        }
+    }
+}
+
+/// Stores a rejected edit in history as a conflict and answers with the server copy.
+async fn reject_conflict(
+    client: &Client,
+    config: &Config,
+    current: &Record,
+    attempted: &Record,
+    device_id: &str,
+) -> HttpResponse {
+    match save_revision(client, attempted, device_id, true).await {
+        Ok(_) => {
+            if let Err(e) = prune_revisions(client, config, &attempted.id).await {
+                error!("{}", e);
+            }
+        }
+        Err(e) => error!("{}", e),
+    }
+    log_event(
+        client,
+        &current.user_id,
+        "record_conflict",
+        &format!(
+            "Edit of record {} from device {} was based on version {}, current is {}",
+            current.id, device_id, attempted.version, current.version
+        ),
+    )
+    .await;
+    conflict_response(current)
+}
 
-    match insert_record(&db, api_record).await {
-        Ok(_) => HttpResponse::Created().json("Record inserted successfully"),
+/// **Moves one of the caller's records to the trash**
+/// It can be recovered until `record_trash_retention_secs` has passed, after
+/// which the purge task deletes it along with its history.
+#[delete("/{record_id}")]
+async fn delete_record(
+    http_req: HttpRequest,
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let expected = match expected_version(&http_req) {
+        Ok(expected) => expected,
+        Err((status, message)) => return HttpResponse::build(status).json(message),
+    };
+    let record_id = path.into_inner();
+    let lease = match next_revision(&client, &claims.user_id).await {
+        Ok(lease) => lease,
         Err(e) => {
-            eprintln!("Error inserting record: {}", e);
-            HttpResponse::InternalServerError().body("Failed to insert record")
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to delete record");
+        }
+    };
+    let revision = lease.revision();
+    let db = client.database("valutx");
+    let collection = get_records_collection(&db);
+    let filter = doc! { "_id": &record_id, "user_id": &claims.user_id, "deleted_at": null };
+    let mut versioned = filter.clone();
+    if let Some(version) = expected {
+        versioned.insert("version", version_filter(version));
+    }
+    match collection
+        .update_one(
+            versioned,
+            doc! {
+                "$set": { "deleted_at": DateTime::now(), "revision": revision },
+                "$inc": { "version": 1 },
+            },
+        )
+        .await
+    {
+        Ok(result) if result.matched_count == 0 => {
+            // Either gone, or changed since the client read it
+            return match collection.find_one(filter).await {
+                Ok(Some(current)) => conflict_response(&current),
+                Ok(None) => HttpResponse::NotFound().json("❌ Record not found"),
+                Err(e) => {
+                    error!("Failed to fetch record: {}", e);
+                    HttpResponse::InternalServerError().json("❌ Failed to delete record")
+                }
+            };
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to delete record: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to delete record");
+        }
+    }
+
+    lease.commit(Some(&claims.device_id)).await;
+    log_event(
+        &client,
+        &claims.user_id,
+        "record_trashed",
+        &format!("Record {} moved to trash from device {}", record_id, claims.device_id),
+    )
+    .await;
+
+    HttpResponse::Ok().json("✅ Record moved to trash")
+}
+
+/// When a record trashed at `deleted_at` is due to be purged
+fn purge_at(deleted_at: DateTime, retention_secs: i64) -> DateTime {
+    DateTime::from_millis(deleted_at.timestamp_millis() + retention_secs * 1000)
+}
+
+/// Records trashed before this are past retention and can no longer be recovered.
+pub fn purge_cutoff(now: DateTime, retention_secs: i64) -> DateTime {
+    DateTime::from_millis(now.timestamp_millis() - retention_secs * 1000)
+}
+
+/// Lists the caller's trashed records with the time each will be purged.
+#[get("/trash")]
+async fn get_trash(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    let cursor = match get_records_collection(&db)
+        .find(doc! { "user_id": &claims.user_id, "deleted_at": { "$ne": null } })
+        .sort(doc! { "deleted_at": -1 })
+        .await
+    {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            error!("Failed to fetch trash: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to fetch trash");
+        }
+    };
+
+    match cursor.try_collect::<Vec<_>>().await {
+        Ok(records) => {
+            let records: Vec<_> = records
+                .iter()
+                .map(|record| {
+                    let mut body = record_json(record);
+                    if let Some(deleted_at) = record.deleted_at {
+                        let purge_time = purge_at(deleted_at, config.record_trash_retention_secs);
+                        body["deleted_at"] = json!(format_date(deleted_at));
+                        body["purge_at"] = json!(format_date(purge_time));
+                    }
+                    body
+                })
+                .collect();
+            HttpResponse::Ok().json(records)
+        }
+        Err(e) => {
+            error!("Failed to collect trash: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch trash")
+        }
+    }
+}
+
+/// Takes one of the caller's records back out of the trash.
+#[post("/{record_id}/recover")]
+async fn recover_record(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let record_id = path.into_inner();
+    let lease = match next_revision(&client, &claims.user_id).await {
+        Ok(lease) => lease,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to recover record");
+        }
+    };
+    let revision = lease.revision();
+    let db = client.database("valutx");
+    // Records past retention are treated as gone even if the purge hasn't run yet
+    let purge_cutoff = purge_cutoff(DateTime::now(), config.record_trash_retention_secs);
+    match get_records_collection(&db)
+        .update_one(
+            doc! {
+                "_id": &record_id,
+                "user_id": &claims.user_id,
+                "deleted_at": { "$ne": null, "$gt": purge_cutoff },
+            },
+            doc! {
+                "$set": { "deleted_at": null, "revision": revision },
+                "$inc": { "version": 1 },
+            },
+        )
+        .await
+    {
+        Ok(result) if result.matched_count == 0 => {
+            return HttpResponse::NotFound().json("❌ Record not found in trash");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to recover record: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to recover record");
+        }
+    }
+
+    lease.commit(Some(&claims.device_id)).await;
+    log_event(
+        &client,
+        &claims.user_id,
+        "record_recovered",
+        &format!("Record {} recovered from trash by device {}", record_id, claims.device_id),
+    )
+    .await;
+
+    HttpResponse::Ok().json("✅ Record recovered")
+}
+
+/// **Permanently deletes a trashed record and its history**
+/// Used by the purge task once the trash retention has passed. A tombstone is
+/// left so devices that haven't synced since learn the record is gone.
+pub async fn purge_record(client: &Client, user_id: &str, record_id: &str) -> Result<(), String> {
+    let db = client.database("valutx");
+    let records = get_records_collection(&db);
+    let filter = doc! { "_id": record_id, "user_id": user_id, "deleted_at": { "$ne": null } };
+    match records.find_one(filter.clone()).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(()),
+        Err(e) => return Err(format!("Failed to fetch record to purge: {}", e)),
+    }
+
+    // The tombstone goes in first: if the deletes below fail, the record is purged
+    // again on the next sweep, but devices are never left without word of it
+    let lease = next_revision(client, user_id).await?;
+    let tombstone = RecordTombstone {
+        user_id: user_id.to_string(),
+        record_id: record_id.to_string(),
+        revision: lease.revision(),
+        deleted_at: DateTime::now(),
+    };
+    get_record_tombstones_collection(&db)
+        .insert_one(&tombstone)
+        .await
+        .map_err(|e| format!("Failed to store record tombstone: {}", e))?;
+
+    let result = records
+        .delete_one(filter)
+        .await
+        .map_err(|e| format!("Failed to purge record: {}", e))?;
+    if result.deleted_count == 0 {
+        // Recovered from the trash in the meantime
+        get_record_tombstones_collection(&db)
+            .delete_one(doc! { "record_id": record_id, "revision": tombstone.revision })
+            .await
+            .map_err(|e| format!("Failed to remove record tombstone: {}", e))?;
+        return Ok(());
+    }
+    get_record_revisions_collection(&db)
+        .delete_many(doc! { "record_id": record_id })
+        .await
+        .map_err(|e| format!("Failed to purge record revisions: {}", e))?;
+    lease.commit(None).await;
+
+    log_event(client, user_id, "record_purged", &format!("Record {} purged from trash", record_id))
+        .await;
+    Ok(())
+}
+
+/// Lists the stored versions of one of the caller's records, newest first.
+#[get("/{record_id}/history")]
+async fn get_record_history(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let record_id = path.into_inner();
+    let db = client.database("valutx");
+    match get_records_collection(&db)
+        .find_one(doc! { "_id": &record_id, "user_id": &claims.user_id, "deleted_at": null })
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json("❌ Record not found"),
+        Err(e) => {
+            error!("Failed to fetch record: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to fetch record history");
+        }
+    }
+
+    let revisions: Result<Vec<_>, _> = match get_record_revisions_collection(&db)
+        .find(doc! { "record_id": &record_id, "user_id": &claims.user_id })
+        .sort(doc! { "created_at": -1 })
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(e) => Err(e),
+    };
+
+    match revisions {
+        Ok(revisions) => {
+            let revisions: Vec<_> = revisions
+                .into_iter()
+                .map(|revision| {
+                    json!({
+                        "revision_id": revision.revision_id,
+                        "record_type": revision.record_type,
+                        "title": revision.title,
+                        "metadata": revision.metadata,
+                        "encrypted_data": revision.encrypted_data,
+                        "wrapped_item_key": revision.wrapped_item_key,
+                        "device_id": revision.device_id,
+                        "version": revision.version,
+                        "conflict": revision.conflict,
+                        "created_at": format_date(revision.created_at),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(revisions)
+        }
+        Err(e) => {
+            error!("Failed to fetch record history: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch record history")
         }
     }
 }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(get_records);
-    cfg.service(create_record);
+/// **Restores a record to one of its earlier versions**
+/// The restore is itself written as a new revision, so it can be undone. Like
+/// an edit, it needs `If-Match` with the version it replaces.
+#[post("/{record_id}/history/{revision_id}/restore")]
+async fn restore_record_revision(
+    http_req: HttpRequest,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let expected = match expected_version(&http_req) {
+        Ok(expected) => expected,
+        Err((status, message)) => return HttpResponse::build(status).json(message),
+    };
+    let (record_id, revision_id) = path.into_inner();
+    let db = client.database("valutx");
+    let collection = get_records_collection(&db);
+    let filter = doc! { "_id": &record_id, "user_id": &claims.user_id, "deleted_at": null };
+
+    let mut record = match collection.find_one(filter.clone()).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return HttpResponse::NotFound().json("❌ Record not found"),
+        Err(e) => {
+            error!("Failed to fetch record: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to restore record");
+        }
+    };
+    if expected.is_some_and(|version| version != record.version) {
+        return conflict_response(&record);
+    }
+    let base_version = record.version;
+
+    let revision = match get_record_revisions_collection(&db)
+        .find_one(doc! {
+            "revision_id": &revision_id,
+            "record_id": &record_id,
+            "user_id": &claims.user_id,
+        })
+        .await
+    {
+        Ok(Some(revision)) => revision,
+        Ok(None) => return HttpResponse::NotFound().json("❌ Revision not found"),
+        Err(e) => {
+            error!("Failed to fetch record revision: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to restore record");
+        }
+    };
+
+    record.record_type = revision.record_type;
+    record.title = revision.title;
+    record.metadata = revision.metadata;
+    record.encrypted_data = revision.encrypted_data;
+    record.wrapped_item_key = revision.wrapped_item_key;
+    record.updated_at = DateTime::now();
+    record.version += 1;
+    let lease = match next_revision(&client, &claims.user_id).await {
+        Ok(lease) => lease,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to restore record");
+        }
+    };
+    record.revision = lease.revision();
+
+    let saved_id = match save_revision(&client, &record, &claims.device_id, false).await {
+        Ok(saved_id) => saved_id,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to restore record");
+        }
+    };
+
+    // The version in the filter catches a write that landed since the read above
+    let mut versioned = filter.clone();
+    versioned.insert("version", version_filter(base_version));
+    match collection.replace_one(versioned, &record).await {
+        Ok(result) if result.matched_count == 0 => {
+            discard_revision(&client, &saved_id).await;
+            return match collection.find_one(filter).await {
+                Ok(Some(latest)) => conflict_response(&latest),
+                Ok(None) => HttpResponse::NotFound().json("❌ Record not found"),
+                Err(e) => {
+                    error!("Failed to fetch record: {}", e);
+                    HttpResponse::InternalServerError().json("❌ Failed to restore record")
+                }
+            };
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to restore record: {}", e);
+            discard_revision(&client, &saved_id).await;
+            return HttpResponse::InternalServerError().json("❌ Failed to restore record");
+        }
+    }
+    if let Err(e) = prune_revisions(&client, &config, &record.id).await {
+        error!("{}", e);
+    }
+
+    lease.commit(Some(&claims.device_id)).await;
+    log_event(
+        &client,
+        &claims.user_id,
+        "record_restored",
+        &format!(
+            "Record {} restored to revision {} from device {}",
+            record_id, revision_id, claims.device_id
+        ),
+    )
+    .await;
+
+    HttpResponse::Ok()
+        .insert_header(record_etag(&record))
+        .json(record_json(&record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::record::RecordMetadata;
+    use actix_web::test::TestRequest;
+
+    fn if_match(value: Option<&str>) -> Result<Option<i64>, (StatusCode, &'static str)> {
+        let mut req = TestRequest::default();
+        if let Some(value) = value {
+            req = req.insert_header((IF_MATCH, value));
+        }
+        expected_version(&req.to_http_request())
+    }
+
+    fn revision(id: &str, conflict: bool) -> RecordRevision {
+        RecordRevision {
+            revision_id: id.to_string(),
+            record_id: "r1".to_string(),
+            user_id: "u1".to_string(),
+            record_type: RecordType::Login,
+            title: "Example".to_string(),
+            metadata: RecordMetadata::default(),
+            encrypted_data: "ciphertext".to_string(),
+            wrapped_item_key: None,
+            device_id: "d1".to_string(),
+            version: 1,
+            conflict,
+            created_at: DateTime::now(),
+        }
+    }
+
+    #[test]
+    fn test_conflicts_do_not_evict_edits() {
+        let history = vec![
+            revision("c3", true),
+            revision("c2", true),
+            revision("e2", false),
+            revision("c1", true),
+            revision("e1", false),
+            revision("e0", false),
+        ];
+        assert_eq!(expired_revisions(&history, 2), vec!["c1", "e0"]);
+        assert!(expired_revisions(&history, 3).is_empty());
+        assert_eq!(expired_revisions(&history, 0).len(), history.len());
+    }
+
+    #[test]
+    fn test_expected_version() {
+        assert_eq!(if_match(None).unwrap_err().0, StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(if_match(Some("*")), Ok(None));
+        assert_eq!(if_match(Some("\"7\"")), Ok(Some(7)));
+        assert_eq!(if_match(Some("W/\"7\"")), Ok(Some(7)));
+        assert_eq!(if_match(Some("\"abc\", \"3\"")), Ok(Some(3)));
+        assert_eq!(if_match(Some("\"abc\"")).unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(if_match(Some("7")).unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_version_filter() {
+        assert_eq!(version_filter(0), Bson::Document(doc! { "$in": [0_i64, null] }));
+        assert_eq!(version_filter(4), Bson::Int64(4));
+    }
+
+    fn record_request(title: &str) -> RecordRequest {
+        RecordRequest {
+            record_type: RecordType::Login,
+            title: title.to_string(),
+            metadata: RecordMetadata::default(),
+            encrypted_data: "ciphertext".to_string(),
+            wrapped_item_key: None,
+            folder_id: None,
+            tag_ids: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_record_ownership() {
+        let created_at = DateTime::from_millis(1_000);
+        let first = new_record("u1", record_request("First"), created_at);
+        let second = new_record("u1", record_request("Second"), created_at);
+        assert_ne!(first.id, second.id);
+        assert_eq!((first.user_id.as_str(), first.version), ("u1", 1));
+
+        let mut current = first;
+        current.version = 4;
+        current.revision = 9;
+        let now = DateTime::now();
+        let edited = edited_record(&current, record_request("Edited"), Some(3), now);
+        assert_eq!((edited.id.as_str(), edited.user_id.as_str()), (current.id.as_str(), "u1"));
+        assert_eq!((edited.created_at, edited.updated_at), (created_at, now));
+        assert_eq!((edited.title.as_str(), edited.version, edited.revision), ("Edited", 3, 9));
+
+        // `If-Match: *` edits whatever version is current
+        assert_eq!(edited_record(&current, record_request("Any"), None, now).version, 4);
+    }
+
+    #[test]
+    fn test_trash_retention() {
+        let now = DateTime::now();
+        let retention = 3600;
+        let cutoff = purge_cutoff(now, retention);
+
+        // Recoverable exactly while the listed purge time is still ahead
+        let recent = DateTime::from_millis(cutoff.timestamp_millis() + 1);
+        assert!(recent > cutoff && purge_at(recent, retention) > now);
+        let stale = DateTime::from_millis(cutoff.timestamp_millis() - 1);
+        assert!(stale < cutoff && purge_at(stale, retention) < now);
+        assert_eq!(purge_at(cutoff, retention), now);
+    }
+
+    #[test]
+    fn test_listing_filter() {
+        let query = |record_type, folder: Option<&str>, tag: Option<&str>| RecordQuery {
+            record_type,
+            folder: folder.map(str::to_string),
+            tag: tag.map(str::to_string),
+        };
+        assert_eq!(
+            listing_filter("u1", &query(None, None, None)),
+            doc! { "user_id": "u1", "deleted_at": null }
+        );
+        assert_eq!(
+            listing_filter("u1", &query(Some(RecordType::Login), Some("f1"), Some("t1"))),
+            doc! {
+                "user_id": "u1",
+                "deleted_at": null,
+                "record_type": "login",
+                "folder_id": "f1",
+                "tag_ids": "t1",
+            }
+        );
+        assert_eq!(
+            listing_filter("u1", &query(Some(RecordType::SecureNote), None, None))
+                .get_document("record_type")
+                .unwrap(),
+            &doc! { "$in": ["secure_note", null] }
+        );
+    }
 }
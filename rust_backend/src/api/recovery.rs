@@ -0,0 +1,286 @@
+use crate::api::sessions::mark_totp_verified;
+use crate::api::totp::{consume_totp_code, TotpCheck};
+use crate::config::config::Config;
+use crate::db::collections::get_recovery_codes_collection;
+use crate::models::device::Device;
+use crate::models::recovery::{RecoveryCode, RecoveryCodeRequest, RecoveryCodeSet};
+use crate::models::totp::TotpCodeRequest;
+use crate::utils::hashing::{hash_password, verify_password};
+use crate::utils::key_management::{generate_recovery_code, normalize_code};
+use crate::utils::logger::log_event;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use log::error;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::ReturnDocument,
+    Client,
+};
+use serde_json::json;
+
+/// Number of codes issued per set
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Failed redeems allowed before redeeming is locked; each one costs up to
+/// `RECOVERY_CODE_COUNT` Argon2 verifications
+const RECOVERY_MAX_FAILURES: i32 = 5;
+const RECOVERY_LOCKOUT_SECS: i64 = 15 * 60;
+
+/// The unused code in `set` matching an already normalized `code`, if any
+fn find_unused<'a>(set: &'a RecoveryCodeSet, code: &str) -> Option<&'a RecoveryCode> {
+    set.codes
+        .iter()
+        .filter(|c| c.used_at.is_none())
+        .find(|c| verify_password(code, &c.hash).unwrap_or(false))
+}
+
+/// **Generates a new set of recovery codes, invalidating the previous set**
+/// Needs a current TOTP code, so a bearer token alone can't mint codes.
+/// The plaintext codes are returned once and never stored.
+#[post("/recovery-codes")]
+async fn regenerate_recovery_codes(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+    req: web::Json<TotpCodeRequest>,
+) -> impl Responder {
+    match consume_totp_code(&client, &config, &claims.user_id, &req.code).await {
+        Ok(TotpCheck::Accepted) => {}
+        Ok(TotpCheck::Rejected) => {
+            log_event(
+                &client,
+                &claims.user_id,
+                "totp_failed",
+                &format!("Rejected recovery code regeneration from device {}", claims.device_id),
+            )
+            .await;
+            return HttpResponse::Unauthorized().json("❌ Invalid TOTP code");
+        }
+        Ok(TotpCheck::NotEnrolled) => {
+            return HttpResponse::Forbidden().json("❌ Enable TOTP before generating recovery codes");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
+
+    let plaintext: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+
+    let mut codes = Vec::with_capacity(plaintext.len());
+    for code in &plaintext {
+        match hash_password(&normalize_code(code)) {
+            Ok(hash) => codes.push(RecoveryCode { hash, used_at: None }),
+            Err(e) => {
+                error!("{}", e);
+                return HttpResponse::InternalServerError().json("❌ Internal server error");
+            }
+        }
+    }
+
+    let set = RecoveryCodeSet {
+        user_id: claims.user_id.clone(),
+        codes,
+        created_at: DateTime::now(),
+        failed_attempts: 0,
+        locked_until: None,
+    };
+
+    let db = client.database("valutx");
+    if let Err(e) = get_recovery_codes_collection(&db)
+        .replace_one(doc! { "user_id": &claims.user_id }, &set)
+        .upsert(true)
+        .await
+    {
+        error!("Failed to store recovery codes: {}", e);
+        return HttpResponse::InternalServerError().json("❌ Internal server error");
+    }
+
+    log_event(
+        &client,
+        &claims.user_id,
+        "recovery_codes_generated",
+        &format!("New set generated from device {}", claims.device_id),
+    )
+    .await;
+
+    HttpResponse::Ok().json(json!({ "codes": plaintext }))
+}
+
+/// Reports how many unused recovery codes remain.
+#[get("/recovery-codes")]
+async fn recovery_codes_status(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    match get_recovery_codes_collection(&db)
+        .find_one(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(Some(set)) => HttpResponse::Ok().json(json!({
+            "remaining": set.codes.iter().filter(|c| c.used_at.is_none()).count(),
+            "created_at": set.created_at.try_to_rfc3339_string().unwrap_or_default(),
+        })),
+        Ok(None) => HttpResponse::Ok().json(json!({ "remaining": 0 })),
+        Err(e) => {
+            error!("Failed to load recovery codes: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
+
+/// **Redeems a recovery code in place of the lost second factor**
+/// The code is spent and the session counts as TOTP-verified for the usual
+/// window, which also allows enrolling a replacement authenticator.
+/// After `RECOVERY_MAX_FAILURES` failed attempts redeeming is locked for a while.
+#[post("/recovery-codes/redeem")]
+async fn redeem_recovery_code(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+    req: web::Json<RecoveryCodeRequest>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    let collection = get_recovery_codes_collection(&db);
+    let code = normalize_code(&req.code);
+    let now = DateTime::now();
+
+    // Count the attempt before verifying so concurrent guesses can't exceed the limit
+    let set = match collection
+        .find_one_and_update(
+            doc! {
+                "user_id": &claims.user_id,
+                "$or": [{ "locked_until": null }, { "locked_until": { "$lte": now } }],
+            },
+            doc! { "$inc": { "failed_attempts": 1 } },
+        )
+        .return_document(ReturnDocument::After)
+        .await
+    {
+        Ok(Some(set)) => set,
+        Ok(None) => {
+            return match collection.count_documents(doc! { "user_id": &claims.user_id }).await {
+                Ok(0) => HttpResponse::Unauthorized().json("❌ Invalid recovery code"),
+                Ok(_) => HttpResponse::TooManyRequests().json("❌ Too many attempts; try again later"),
+                Err(e) => {
+                    error!("Failed to load recovery codes: {}", e);
+                    HttpResponse::InternalServerError().json("❌ Internal server error")
+                }
+            };
+        }
+        Err(e) => {
+            error!("Failed to load recovery codes: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    if set.failed_attempts > RECOVERY_MAX_FAILURES {
+        let locked_until =
+            DateTime::from_millis(now.timestamp_millis() + RECOVERY_LOCKOUT_SECS * 1000);
+        if let Err(e) = collection
+            .update_one(
+                doc! { "user_id": &claims.user_id },
+                doc! { "$set": { "locked_until": locked_until, "failed_attempts": 0 } },
+            )
+            .await
+        {
+            error!("Failed to lock recovery codes: {}", e);
+        }
+        log_event(
+            &client,
+            &claims.user_id,
+            "recovery_codes_locked",
+            &format!("Too many failed recovery codes from device {}", claims.device_id),
+        )
+        .await;
+        return HttpResponse::TooManyRequests().json("❌ Too many attempts; try again later");
+    }
+
+    let hash = match find_unused(&set, &code) {
+        Some(matched) => matched.hash.clone(),
+        None => {
+            log_event(
+                &client,
+                &claims.user_id,
+                "recovery_code_failed",
+                &format!("Invalid code from device {}", claims.device_id),
+            )
+            .await;
+            return HttpResponse::Unauthorized().json("❌ Invalid recovery code");
+        }
+    };
+
+    // Spend the code; the filter makes a concurrent second use fail
+    match collection
+        .update_one(
+            doc! {
+                "user_id": &claims.user_id,
+                "codes": { "$elemMatch": { "hash": &hash, "used_at": null } },
+            },
+            doc! { "$set": { "codes.$.used_at": DateTime::now(), "failed_attempts": 0 } },
+        )
+        .await
+    {
+        Ok(result) if result.matched_count == 1 => {}
+        Ok(_) => return HttpResponse::Unauthorized().json("❌ Invalid recovery code"),
+        Err(e) => {
+            error!("Failed to spend recovery code: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
+
+    let until = DateTime::from_millis(now.timestamp_millis() + config.totp_session_secs * 1000);
+    if let Err(e) = mark_totp_verified(&client, &claims.jti, until).await {
+        error!("{}", e);
+        return HttpResponse::InternalServerError().json("❌ Internal server error");
+    }
+
+    let remaining = set.codes.iter().filter(|c| c.used_at.is_none()).count() - 1;
+    log_event(
+        &client,
+        &claims.user_id,
+        "recovery_code_used",
+        &format!(
+            "Second factor satisfied from device {}; {} code(s) remaining",
+            claims.device_id, remaining
+        ),
+    )
+    .await;
+
+    HttpResponse::Ok().json(json!({ "remaining": remaining }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_set(codes: &[(&str, bool)]) -> RecoveryCodeSet {
+        RecoveryCodeSet {
+            user_id: "u1".to_string(),
+            codes: codes
+                .iter()
+                .map(|(code, used)| RecoveryCode {
+                    hash: hash_password(&normalize_code(code)).unwrap(),
+                    used_at: used.then(DateTime::now),
+                })
+                .collect(),
+            created_at: DateTime::now(),
+            failed_attempts: 0,
+            locked_until: None,
+        }
+    }
+
+    #[test]
+    fn test_recovery_codes_are_single_use() {
+        let fresh = generate_recovery_code();
+        assert_eq!(fresh.len(), 11);
+        let set = code_set(&[(&fresh, false), ("K7M2Q-XW9RT", true)]);
+
+        // Typed in lowercase and without the dash it still matches
+        let typed = normalize_code(&fresh.to_lowercase().replace('-', " "));
+        assert!(find_unused(&set, &typed).is_some());
+        assert!(find_unused(&set, &normalize_code("K7M2Q-XW9RT")).is_none());
+        assert!(find_unused(&set, &normalize_code("AAAAA-BBBBB")).is_none());
+    }
+}
@@ -1,19 +1,146 @@
+use crate::config::config::Config;
+use crate::db::collections::{get_devices_collection, get_users_collection, is_duplicate_key_error};
 use crate::models::auth::AuthRequest;
-use actix_web::{post, web, HttpResponse, Responder};
+use crate::models::device::{DeviceFingerprint, DeviceStatus, TrustedDevice};
+use crate::models::key::KdfParams;
+use crate::models::user::User;
+use crate::utils::fingerprint::{observe, to_stored};
+use crate::utils::hashing::{check_password_strength, hash_password};
+use crate::utils::kdf::new_kdf_params;
+use crate::utils::logger::log_event;
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use log::{error, info};
+use mongodb::{bson::DateTime, Client};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Reasons a registration can be refused
+#[derive(Debug)]
+pub enum RegistrationError {
+    InvalidInput(String),
+    WeakPassword(String),
+    UsernameTaken,
+    Internal(String),
+}
+
+impl RegistrationError {
+    /// Maps the error to a structured JSON response
+    pub fn to_response(&self) -> HttpResponse {
+        match self {
+            RegistrationError::InvalidInput(msg) => HttpResponse::BadRequest()
+                .json(json!({ "error": "invalid_input", "message": msg })),
+            RegistrationError::WeakPassword(msg) => HttpResponse::BadRequest()
+                .json(json!({ "error": "weak_password", "message": msg })),
+            RegistrationError::UsernameTaken => HttpResponse::Conflict().json(json!({
+                "error": "username_taken",
+                "message": "An account with this username already exists"
+            })),
+            RegistrationError::Internal(_) => HttpResponse::InternalServerError()
+                .json(json!({ "error": "internal", "message": "Internal server error" })),
+        }
+    }
+}
 
 #[post("/register")]
-pub async fn register(req: web::Json<AuthRequest>) -> impl Responder {
+pub async fn register(
+    http_req: HttpRequest,
+    db_client: web::Data<Client>,
+    config: web::Data<Config>,
+    req: web::Json<AuthRequest>,
+) -> impl Responder {
     let register_request = req.into_inner();
+    let fingerprint = to_stored(&observe(&http_req, &config), &config);
+
+    match create_user(
+        &db_client,
+        &register_request.username,
+        &register_request.password,
+        &register_request.device_id,
+        fingerprint,
+        new_kdf_params(&config),
+    )
+    .await
+    {
+        Ok(user) => HttpResponse::Ok().json(json!({
+            "user_id": user.user_id,
+            "username": user.username,
+            "device_id": user.device_id,
+        })),
+        Err(e) => {
+            if let RegistrationError::Internal(msg) = &e {
+                error!("Registration error: {}", msg);
+            }
+            e.to_response()
+        }
+    }
+}
 
-    // Use the password field by calculating its length (for demonstration)
-    let password_length = register_request.password.len();
+/// **Creates a new user account**
+/// Hashes the password with Argon2id and binds `device_id` as the trusted device,
+/// recording its fingerprint when the client supplied one. `kdf` holds the
+/// parameters the client will derive the master key with.
+pub async fn create_user(
+    db_client: &Client,
+    username: &str,
+    password: &str,
+    device_id: &str,
+    fingerprint: Option<DeviceFingerprint>,
+    kdf: KdfParams,
+) -> Result<User, RegistrationError> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err(RegistrationError::InvalidInput("Username cannot be empty".to_string()));
+    }
+    if device_id.trim().is_empty() {
+        return Err(RegistrationError::InvalidInput("Device id cannot be empty".to_string()));
+    }
+    check_password_strength(password).map_err(RegistrationError::WeakPassword)?;
 
-    println!(
-        "Registering user: {} with device id: {} (password length: {})",
-        register_request.username, register_request.device_id, password_length
-    );
+    let password_hash = hash_password(password).map_err(RegistrationError::Internal)?;
+    let user = User {
+        user_id: Uuid::new_v4().to_string(),
+        username: username.to_string(),
+        password_hash,
+        device_id: device_id.to_string(),
+        kdf: Some(kdf),
+    };
 
-    // TODO: Store user in DB and handle password hashing securely
+    let db = db_client.database("valutx");
+    match get_users_collection(&db).insert_one(&user).await {
+        Ok(_) => {
+            let device = TrustedDevice {
+                user_id: user.user_id.clone(),
+                device_id: user.device_id.clone(),
+                name: None,
+                status: DeviceStatus::Approved,
+                fingerprint,
+                approved_at: Some(DateTime::now()),
+                last_seen_at: None,
+                requested_at: None,
+                pending_expires_at: None,
+                last_mismatch: None,
+            };
+            if let Err(e) = get_devices_collection(&db).insert_one(&device).await {
+                return Err(RegistrationError::Internal(format!(
+                    "Failed to store trusted device: {}",
+                    e
+                )));
+            }
 
-    HttpResponse::Ok().json("Registration successful")
+            info!("Registered user '{}'.", user.username);
+            log_event(
+                db_client,
+                &user.user_id,
+                "user_registered",
+                &format!("Trusted device {}", user.device_id),
+            )
+            .await;
+            Ok(user)
+        }
+        Err(e) if is_duplicate_key_error(&e) => Err(RegistrationError::UsernameTaken),
+        Err(e) => Err(RegistrationError::Internal(format!(
+            "Failed to insert user: {}",
+            e
+        ))),
+    }
 }
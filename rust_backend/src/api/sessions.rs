@@ -0,0 +1,187 @@
+use crate::db::collections::{get_refresh_tokens_collection, get_sessions_collection};
+use crate::models::device::Device;
+use crate::utils::logger::log_event;
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use futures::TryStreamExt;
+use log::error;
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    Client,
+};
+use serde_json::json;
+
+/// **Revokes every active session matching `filter`**
+/// Also revokes the sessions' refresh token families so they cannot be renewed.
+/// Returns the number of sessions revoked.
+pub async fn revoke_sessions(client: &Client, filter: Document) -> Result<u64, String> {
+    let db = client.database("valutx");
+    let sessions = get_sessions_collection(&db);
+
+    let mut filter = filter;
+    filter.insert("revoked", false);
+
+    let session_ids: Vec<String> = sessions
+        .find(filter.clone())
+        .await
+        .map_err(|e| format!("Failed to load sessions: {}", e))?
+        .map_ok(|session| session.session_id)
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to load sessions: {}", e))?;
+
+    if session_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let result = sessions
+        .update_many(
+            doc! { "session_id": { "$in": &session_ids } },
+            doc! { "$set": { "revoked": true } },
+        )
+        .await
+        .map_err(|e| format!("Failed to revoke sessions: {}", e))?;
+
+    get_refresh_tokens_collection(&db)
+        .update_many(
+            doc! { "family_id": { "$in": &session_ids } },
+            doc! { "$set": { "revoked": true } },
+        )
+        .await
+        .map_err(|e| format!("Failed to revoke refresh tokens: {}", e))?;
+
+    Ok(result.modified_count)
+}
+
+/// **Marks the session behind access token `jti` as TOTP-verified until `until`**
+/// The window follows the session through token refreshes.
+pub async fn mark_totp_verified(client: &Client, jti: &str, until: DateTime) -> Result<(), String> {
+    let db = client.database("valutx");
+    get_sessions_collection(&db)
+        .update_one(
+            doc! { "jti": jti, "revoked": false },
+            doc! { "$set": { "totp_verified_until": until } },
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Failed to mark session TOTP-verified: {}", e))
+}
+
+/// Whether the session behind access token `jti` is inside its TOTP window.
+pub async fn is_totp_verified(client: &Client, jti: &str) -> Result<bool, String> {
+    let db = client.database("valutx");
+    get_sessions_collection(&db)
+        .count_documents(doc! {
+            "jti": jti,
+            "revoked": false,
+            "totp_verified_until": { "$gt": DateTime::now() },
+        })
+        .await
+        .map(|count| count > 0)
+        .map_err(|e| format!("Failed to check session TOTP window: {}", e))
+}
+
+fn format_date(date: DateTime) -> String {
+    date.try_to_rfc3339_string().unwrap_or_default()
+}
+
+/// Ends the session the request was made with.
+#[post("/logout")]
+async fn logout(client: web::Data<Client>, claims: web::ReqData<Device>) -> impl Responder {
+    match revoke_sessions(&client, doc! { "jti": &claims.jti, "user_id": &claims.user_id }).await {
+        Ok(_) => {
+            log_event(&client, &claims.user_id, "logout", &format!("Device {}", claims.device_id))
+                .await;
+            HttpResponse::Ok().json("✅ Logged out")
+        }
+        Err(e) => {
+            error!("Logout error: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
+
+/// Lists the caller's active sessions.
+#[get("/sessions")]
+async fn list_sessions(client: web::Data<Client>, claims: web::ReqData<Device>) -> impl Responder {
+    let db = client.database("valutx");
+    let cursor = match get_sessions_collection(&db)
+        .find(doc! { "user_id": &claims.user_id, "revoked": false })
+        .await
+    {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            error!("Failed to fetch sessions: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to fetch sessions");
+        }
+    };
+
+    match cursor.try_collect::<Vec<_>>().await {
+        Ok(sessions) => {
+            let sessions: Vec<_> = sessions
+                .into_iter()
+                .map(|session| {
+                    json!({
+                        "session_id": session.session_id,
+                        "device_id": session.device_id,
+                        "created_at": format_date(session.created_at),
+                        "last_refreshed_at": format_date(session.last_refreshed_at),
+                        "expires_at": format_date(session.expires_at),
+                        "current": session.jti == claims.jti,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(sessions)
+        }
+        Err(e) => {
+            error!("Failed to collect sessions: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch sessions")
+        }
+    }
+}
+
+/// Revokes one of the caller's sessions.
+#[delete("/sessions/{session_id}")]
+async fn revoke_session(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let session_id = path.into_inner();
+    match revoke_sessions(&client, doc! { "session_id": &session_id, "user_id": &claims.user_id })
+        .await
+    {
+        Ok(0) => HttpResponse::NotFound().json("❌ Session not found"),
+        Ok(_) => {
+            log_event(&client, &claims.user_id, "session_revoked", &session_id).await;
+            HttpResponse::Ok().json("✅ Session revoked")
+        }
+        Err(e) => {
+            error!("Session revocation error: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
+
+/// Revokes all of the caller's sessions, including the current one.
+#[delete("/sessions")]
+async fn revoke_all_sessions(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+) -> impl Responder {
+    match revoke_sessions(&client, doc! { "user_id": &claims.user_id }).await {
+        Ok(count) => {
+            log_event(
+                &client,
+                &claims.user_id,
+                "sessions_revoked",
+                &format!("{} session(s) revoked from device {}", count, claims.device_id),
+            )
+            .await;
+            HttpResponse::Ok().json(json!({ "revoked": count }))
+        }
+        Err(e) => {
+            error!("Session revocation error: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use futures::TryStreamExt;
+use log::error;
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    options::ReturnDocument,
+    Client,
+};
+use serde_json::json;
+use crate::api::records::record_json;
+use crate::db::collections::{
+    get_record_tombstones_collection, get_records_collection, get_sync_counters_collection,
+    get_sync_states_collection,
+};
+use crate::models::device::Device;
+use crate::models::event::VaultEvent;
+use crate::models::sync::{SyncCounter, SyncQuery};
+use crate::utils::events::publish;
+
+/// How long a taken revision holds back the sync high-water mark. A lease older
+/// than this belongs to a write that died without releasing it.
+const REVISION_LEASE_SECS: i64 = 60;
+
+/// **A revision taken from the user's counter for a write in progress**
+/// Sync never reports a high-water mark at or past an open lease, so a device
+/// can't skip over a write that commits after a later one. `commit` releases
+/// the lease once the write is stored; dropping it releases it too.
+pub struct RevisionLease {
+    client: Client,
+    user_id: String,
+    revision: i64,
+    open: bool,
+}
+
+impl RevisionLease {
+    pub fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// Releases the lease after its write landed and tells the user's connected devices.
+    /// `device_id` is the device that made the change, `None` for server jobs.
+    pub async fn commit(mut self, device_id: Option<&str>) {
+        self.open = false;
+        if let Err(e) = release_revision(&self.client, &self.user_id, self.revision).await {
+            error!("{}", e);
+        }
+        publish(
+            &self.user_id,
+            VaultEvent::VaultChanged { revision: self.revision, device_id: device_id.map(str::to_string) },
+        );
+    }
+}
+
+impl Drop for RevisionLease {
+    fn drop(&mut self) {
+        if !self.open {
+            return;
+        }
+        // Without a runtime the lease simply goes stale
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let (client, user_id, revision) = (self.client.clone(), self.user_id.clone(), self.revision);
+            handle.spawn(async move {
+                if let Err(e) = release_revision(&client, &user_id, revision).await {
+                    error!("{}", e);
+                }
+            });
+        }
+    }
+}
+
+/// **Takes the next value of the user's change counter**
+/// Every write to a user's records is stamped with one, so devices can ask
+/// for everything after the revision they last saw. The revision stays
+/// pending until the returned lease is committed or dropped.
+pub async fn next_revision(client: &Client, user_id: &str) -> Result<RevisionLease, String> {
+    let db = client.database("valutx");
+    let now = DateTime::now();
+    let stale = DateTime::from_millis(now.timestamp_millis() - REVISION_LEASE_SECS * 1000);
+    // One atomic update bumps the counter, records the lease and drops stale ones
+    let update = vec![
+        doc! { "$set": { "revision": { "$add": [{ "$ifNull": ["$revision", 0_i64] }, 1_i64] } } },
+        doc! { "$set": { "pending": { "$concatArrays": [
+            {
+                "$filter": {
+                    "input": { "$ifNull": ["$pending", []] },
+                    "cond": { "$gt": ["$$this.started_at", stale] },
+                },
+            },
+            [{ "revision": "$revision", "started_at": now }],
+        ] } } },
+    ];
+    let revision = get_sync_counters_collection(&db)
+        .find_one_and_update(doc! { "user_id": user_id }, update)
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .await
+        .map_err(|e| format!("Failed to advance sync revision: {}", e))?
+        .map(|counter| counter.revision)
+        .ok_or_else(|| "Sync counter missing after upsert".to_string())?;
+    Ok(RevisionLease { client: client.clone(), user_id: user_id.to_string(), revision, open: true })
+}
+
+async fn release_revision(client: &Client, user_id: &str, revision: i64) -> Result<(), String> {
+    let db = client.database("valutx");
+    get_sync_counters_collection(&db)
+        .update_one(
+            doc! { "user_id": user_id },
+            doc! { "$pull": { "pending": { "revision": revision } } },
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Failed to release sync revision {}: {}", revision, e))
+}
+
+/// **Highest revision below which every write has landed, 0 before the first write**
+/// Changes stamped above it may still be in flight and are left for the next sync.
+async fn committed_revision(client: &Client, user_id: &str) -> Result<i64, String> {
+    let db = client.database("valutx");
+    let counter = get_sync_counters_collection(&db)
+        .find_one(doc! { "user_id": user_id })
+        .await
+        .map_err(|e| format!("Failed to load sync revision: {}", e))?;
+    Ok(counter.map_or(0, |counter| high_water_mark(&counter, DateTime::now())))
+}
+
+/// The counter's revision, held just below the oldest lease still open at `now`.
+/// Leases past `REVISION_LEASE_SECS` no longer hold it back.
+fn high_water_mark(counter: &SyncCounter, now: DateTime) -> i64 {
+    let stale = now.timestamp_millis() - REVISION_LEASE_SECS * 1000;
+    counter
+        .pending
+        .iter()
+        .filter(|lease| lease.started_at.timestamp_millis() > stale)
+        .map(|lease| lease.revision - 1)
+        .fold(counter.revision, i64::min)
+}
+
+/// Revisions a sync from `since` up to `revision` covers. A full sync starts at
+/// 0 inclusive, which takes in records backfilled at revision 0.
+fn revision_range(since: i64, revision: i64) -> Document {
+    if since == 0 {
+        doc! { "$gte": 0_i64, "$lte": revision }
+    } else {
+        doc! { "$gt": since, "$lte": revision }
+    }
+}
+
+/// **Returns what changed in the caller's vault since revision `since`**
+/// Records are split into created and updated; records moved to the trash or
+/// purged come back as tombstones. The returned `revision` is the `since` for
+/// the next call, and is saved as the device's sync state.
+#[get("")]
+async fn sync(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    query: web::Query<SyncQuery>,
+) -> impl Responder {
+    let since = query.since.max(0);
+    let db = client.database("valutx");
+
+    // Read the high-water mark first; changes stamped after it are left for the next sync
+    let revision = match committed_revision(&client, &claims.user_id).await {
+        Ok(revision) => revision,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Sync failed");
+        }
+    };
+
+    let range = revision_range(since, revision);
+
+    let changed: Result<Vec<_>, _> = match get_records_collection(&db)
+        .find(doc! { "user_id": &claims.user_id, "revision": range.clone() })
+        .sort(doc! { "revision": 1 })
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(e) => Err(e),
+    };
+    let changed = match changed {
+        Ok(changed) => changed,
+        Err(e) => {
+            error!("Failed to load changed records: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Sync failed");
+        }
+    };
+
+    let purged: Result<Vec<_>, _> = match get_record_tombstones_collection(&db)
+        .find(doc! { "user_id": &claims.user_id, "revision": range })
+        .sort(doc! { "revision": 1 })
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(e) => Err(e),
+    };
+    let purged = match purged {
+        Ok(purged) => purged,
+        Err(e) => {
+            error!("Failed to load record tombstones: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Sync failed");
+        }
+    };
+
+    let mut created = Vec::new();
+    let mut updated = Vec::new();
+    let mut deleted = Vec::new();
+    for record in &changed {
+        match record.deleted_at {
+            Some(deleted_at) => deleted.push(json!({
+                "id": record.id,
+                "revision": record.revision,
+                "deleted_at": deleted_at.try_to_rfc3339_string().unwrap_or_default(),
+                "purged": false,
+            })),
+            None if since == 0 || record.created_revision > since => created.push(record_json(record)),
+            None => updated.push(record_json(record)),
+        }
+    }
+    deleted.extend(purged.iter().map(|tombstone| {
+        json!({
+            "id": tombstone.record_id,
+            "revision": tombstone.revision,
+            "deleted_at": tombstone.deleted_at.try_to_rfc3339_string().unwrap_or_default(),
+            "purged": true,
+        })
+    }));
+
+    if let Err(e) = get_sync_states_collection(&db)
+        .update_one(
+            doc! { "user_id": &claims.user_id, "device_id": &claims.device_id },
+            doc! { "$set": { "revision": revision, "synced_at": DateTime::now() } },
+        )
+        .upsert(true)
+        .await
+    {
+        error!("Failed to store sync state: {}", e);
+    }
+
+    HttpResponse::Ok().json(json!({
+        "revision": revision,
+        "created": created,
+        "updated": updated,
+        "deleted": deleted,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::sync::PendingRevision;
+
+    fn lease(revision: i64, started_at: DateTime) -> PendingRevision {
+        PendingRevision { revision, started_at }
+    }
+
+    #[test]
+    fn test_high_water_mark() {
+        let now = DateTime::now();
+        let stale = DateTime::from_millis(now.timestamp_millis() - (REVISION_LEASE_SECS + 1) * 1000);
+        let mut counter = SyncCounter { user_id: "u1".to_string(), revision: 10, pending: Vec::new() };
+        assert_eq!(high_water_mark(&counter, now), 10);
+
+        // An open lease holds the mark just below it, whichever order writes finish in
+        counter.pending = vec![lease(9, now), lease(7, now)];
+        assert_eq!(high_water_mark(&counter, now), 6);
+
+        // A lease whose write died stops holding it back once stale
+        counter.pending = vec![lease(4, stale), lease(9, now)];
+        assert_eq!(high_water_mark(&counter, now), 8);
+        counter.pending = vec![lease(4, stale)];
+        assert_eq!(high_water_mark(&counter, now), 10);
+    }
+
+    #[test]
+    fn test_revision_range() {
+        assert_eq!(revision_range(0, 5), doc! { "$gte": 0_i64, "$lte": 5_i64 });
+        assert_eq!(revision_range(3, 5), doc! { "$gt": 3_i64, "$lte": 5_i64 });
+    }
+}
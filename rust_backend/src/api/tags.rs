@@ -0,0 +1,171 @@
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use futures::TryStreamExt;
+use log::error;
+use mongodb::{
+    bson::{doc, DateTime},
+    Client,
+};
+use serde_json::json;
+use uuid::Uuid;
+use crate::api::sync::next_revision;
+use crate::db::collections::{get_records_collection, get_tags_collection};
+use crate::models::device::Device;
+use crate::models::folder::{LabelRequest, Tag};
+use crate::utils::logger::log_event;
+
+fn tag_json(tag: &Tag) -> serde_json::Value {
+    json!({
+        "tag_id": tag.tag_id,
+        "encrypted_name": tag.encrypted_name,
+        "created_at": tag.created_at.try_to_rfc3339_string().unwrap_or_default(),
+    })
+}
+
+/// Lists the caller's tags.
+#[get("")]
+async fn list_tags(client: web::Data<Client>, claims: web::ReqData<Device>) -> impl Responder {
+    let db = client.database("valutx");
+    let cursor = match get_tags_collection(&db)
+        .find(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            error!("Failed to fetch tags: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to fetch tags");
+        }
+    };
+
+    match cursor.try_collect::<Vec<_>>().await {
+        Ok(tags) => HttpResponse::Ok().json(tags.iter().map(tag_json).collect::<Vec<_>>()),
+        Err(e) => {
+            error!("Failed to collect tags: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to fetch tags")
+        }
+    }
+}
+
+/// Creates a tag.
+#[post("")]
+async fn create_tag(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    req: web::Json<LabelRequest>,
+) -> impl Responder {
+    if req.encrypted_name.trim().is_empty() {
+        return HttpResponse::BadRequest().json("❌ Tag name cannot be empty");
+    }
+
+    let tag = Tag {
+        tag_id: Uuid::new_v4().to_string(),
+        user_id: claims.user_id.clone(),
+        encrypted_name: req.into_inner().encrypted_name,
+        created_at: DateTime::now(),
+    };
+    let db = client.database("valutx");
+    match get_tags_collection(&db).insert_one(&tag).await {
+        Ok(_) => HttpResponse::Created().json(tag_json(&tag)),
+        Err(e) => {
+            error!("Failed to create tag: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to create tag")
+        }
+    }
+}
+
+/// Renames a tag.
+#[put("/{tag_id}")]
+async fn rename_tag(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+    req: web::Json<LabelRequest>,
+) -> impl Responder {
+    if req.encrypted_name.trim().is_empty() {
+        return HttpResponse::BadRequest().json("❌ Tag name cannot be empty");
+    }
+
+    let db = client.database("valutx");
+    match get_tags_collection(&db)
+        .update_one(
+            doc! { "tag_id": path.into_inner(), "user_id": &claims.user_id },
+            doc! { "$set": { "encrypted_name": &req.encrypted_name } },
+        )
+        .await
+    {
+        Ok(result) if result.matched_count == 0 => HttpResponse::NotFound().json("❌ Tag not found"),
+        Ok(_) => HttpResponse::Ok().json("✅ Tag renamed"),
+        Err(e) => {
+            error!("Failed to rename tag: {}", e);
+            HttpResponse::InternalServerError().json("❌ Failed to rename tag")
+        }
+    }
+}
+
+/// Deletes a tag and removes it from every record that carries it.
+#[delete("/{tag_id}")]
+async fn delete_tag(
+    client: web::Data<Client>,
+    claims: web::ReqData<Device>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let tag_id = path.into_inner();
+    let db = client.database("valutx");
+    let tags = get_tags_collection(&db);
+    let tag_filter = doc! { "tag_id": &tag_id, "user_id": &claims.user_id };
+    match tags.find_one(tag_filter.clone()).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json("❌ Tag not found"),
+        Err(e) => {
+            error!("Failed to fetch tag: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to delete tag");
+        }
+    }
+
+    // Records are untagged before the tag goes, so a failure leaves it in place for a retry
+    let lease = match next_revision(&client, &claims.user_id).await {
+        Ok(lease) => lease,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to delete tag");
+        }
+    };
+    let revision = lease.revision();
+    let untagged = match get_records_collection(&db)
+        .update_many(
+            doc! { "user_id": &claims.user_id, "tag_ids": &tag_id },
+            doc! {
+                "$pull": { "tag_ids": &tag_id },
+                "$set": { "revision": revision },
+                "$inc": { "version": 1 },
+            },
+        )
+        .await
+    {
+        Ok(result) => result.modified_count,
+        Err(e) => {
+            error!("Failed to untag records: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Failed to delete tag");
+        }
+    };
+
+    if let Err(e) = tags.delete_one(tag_filter).await {
+        error!("Failed to delete tag: {}", e);
+        return HttpResponse::InternalServerError().json("❌ Failed to delete tag");
+    }
+
+    if untagged > 0 {
+        lease.commit(Some(&claims.device_id)).await;
+    }
+    log_event(
+        &client,
+        &claims.user_id,
+        "tag_deleted",
+        &format!(
+            "Tag {} deleted from device {}; {} record(s) untagged",
+            tag_id, claims.device_id, untagged
+        ),
+    )
+    .await;
+
+    HttpResponse::Ok().json(json!({ "untagged": untagged }))
+}
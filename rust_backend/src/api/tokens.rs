@@ -0,0 +1,243 @@
+use crate::api::devices::{check_device_access, DeviceAccess};
+use crate::api::sessions::revoke_sessions;
+use crate::config::config::Config;
+use crate::db::collections::{get_refresh_tokens_collection, get_sessions_collection};
+use crate::models::auth::{RefreshRequest, RefreshToken, SessionTokens};
+use crate::models::session::Session;
+use crate::utils::hashing::hash_token;
+use crate::utils::logger::log_event;
+use crate::utils::token::{generate_refresh_token, issue_token};
+use actix_web::{post, web, HttpResponse, Responder};
+use log::{error, warn};
+use mongodb::{
+    bson::{doc, DateTime},
+    Client,
+};
+use std::fmt;
+use uuid::Uuid;
+
+/// Reasons a session can't be issued or rotated
+#[derive(Debug)]
+pub enum SessionError {
+    /// The session being rotated was revoked or logged out
+    Revoked,
+    Internal(String),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Revoked => write!(f, "Session is no longer active"),
+            SessionError::Internal(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// **Issues an access token and a refresh token for a user/device pair**
+/// Pass the `family_id` of the token being rotated, or `None` to start a new session.
+/// The refresh token family doubles as the session id.
+pub async fn issue_session(
+    client: &Client,
+    config: &Config,
+    user_id: &str,
+    device_id: &str,
+    family_id: Option<String>,
+) -> Result<SessionTokens, SessionError> {
+    let db = client.database("valutx");
+    let jti = Uuid::new_v4().to_string();
+    let token = issue_token(user_id, device_id, &jti, &config.jwt_secret, config.jwt_ttl_secs)
+        .map_err(SessionError::Internal)?;
+    let refresh_token = generate_refresh_token();
+
+    let now = DateTime::now();
+    let expires_at = DateTime::from_millis(now.timestamp_millis() + config.refresh_ttl_secs * 1000);
+
+    let family_id = match family_id {
+        Some(family_id) => {
+            // Point the session at the new access token; the previous one stops working
+            let result = get_sessions_collection(&db)
+                .update_one(
+                    doc! { "session_id": &family_id, "revoked": false },
+                    doc! { "$set": { "jti": &jti, "last_refreshed_at": now, "expires_at": expires_at } },
+                )
+                .await
+                .map_err(|e| SessionError::Internal(format!("Failed to update session: {}", e)))?;
+            if result.matched_count == 0 {
+                return Err(SessionError::Revoked);
+            }
+            family_id
+        }
+        None => {
+            let session = Session {
+                session_id: Uuid::new_v4().to_string(),
+                jti: jti.clone(),
+                user_id: user_id.to_string(),
+                device_id: device_id.to_string(),
+                revoked: false,
+                created_at: now,
+                last_refreshed_at: now,
+                expires_at,
+                totp_verified_until: None,
+            };
+            get_sessions_collection(&db)
+                .insert_one(&session)
+                .await
+                .map_err(|e| SessionError::Internal(format!("Failed to store session: {}", e)))?;
+            session.session_id
+        }
+    };
+
+    let stored = RefreshToken {
+        token_hash: hash_token(&refresh_token),
+        family_id,
+        user_id: user_id.to_string(),
+        device_id: device_id.to_string(),
+        used: false,
+        revoked: false,
+        created_at: now,
+        expires_at,
+    };
+
+    get_refresh_tokens_collection(&db)
+        .insert_one(&stored)
+        .await
+        .map_err(|e| SessionError::Internal(format!("Failed to store refresh token: {}", e)))?;
+
+    Ok(SessionTokens {
+        token,
+        token_type: "Bearer".to_string(),
+        expires_in: config.jwt_ttl_secs,
+        refresh_token,
+    })
+}
+
+/// A token that was already rotated but not yet revoked; seeing it again means it leaked.
+/// Revoked tokens are ignored, since their family has already been shut down.
+fn is_replay(token: &RefreshToken) -> bool {
+    token.used && !token.revoked
+}
+
+/// **Rotates a refresh token**
+/// Each refresh token is single use. Presenting one that was already rotated
+/// means it leaked, so the whole family is revoked and the client must log in again.
+#[post("/token/refresh")]
+async fn refresh(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    req: web::Json<RefreshRequest>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    let collection = get_refresh_tokens_collection(&db);
+    let token_hash = hash_token(&req.refresh_token);
+
+    // Atomically claim the token so concurrent refreshes cannot both succeed
+    let claimed = match collection
+        .find_one_and_update(
+            doc! {
+                "token_hash": &token_hash,
+                "used": false,
+                "revoked": false,
+                "expires_at": { "$gt": DateTime::now() },
+            },
+            doc! { "$set": { "used": true } },
+        )
+        .await
+    {
+        Ok(claimed) => claimed,
+        Err(e) => {
+            error!("Refresh token lookup failed: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let stored = match claimed {
+        Some(stored) => stored,
+        None => {
+            // Unknown, expired, revoked or already used. Replay of a used token revokes the family.
+            if let Ok(Some(replayed)) = collection.find_one(doc! { "token_hash": &token_hash }).await {
+                if is_replay(&replayed) {
+                    warn!(
+                        "Refresh token reuse detected for user '{}' on device '{}'.",
+                        replayed.user_id, replayed.device_id
+                    );
+                    if let Err(e) =
+                        revoke_sessions(&client, doc! { "session_id": &replayed.family_id }).await
+                    {
+                        error!("{}", e);
+                    }
+                    log_event(
+                        &client,
+                        &replayed.user_id,
+                        "refresh_token_reuse",
+                        &format!("Session revoked for device {}", replayed.device_id),
+                    )
+                    .await;
+                }
+            }
+            return HttpResponse::Unauthorized().json("❌ Invalid refresh token");
+        }
+    };
+
+    // An expired or removed device can't keep renewing its session
+    match check_device_access(&client, &config, &stored.user_id, &stored.device_id).await {
+        Ok(DeviceAccess::Active(_)) => {}
+        Ok(access) => {
+            if let Err(e) = revoke_sessions(&client, doc! { "session_id": &stored.family_id }).await {
+                error!("{}", e);
+            }
+            return match access {
+                DeviceAccess::ReapprovalRequired => {
+                    HttpResponse::Forbidden().json("❌ Device reapproval required")
+                }
+                _ => HttpResponse::Unauthorized().json("❌ Unrecognized device"),
+            };
+        }
+        Err(e) => {
+            error!("Refresh error: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
+
+    match issue_session(
+        &client,
+        &config,
+        &stored.user_id,
+        &stored.device_id,
+        Some(stored.family_id),
+    )
+    .await
+    {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(SessionError::Revoked) => HttpResponse::Unauthorized().json("❌ Session revoked"),
+        Err(e) => {
+            error!("Refresh error: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refresh_token(used: bool, revoked: bool) -> RefreshToken {
+        RefreshToken {
+            token_hash: "hash".to_string(),
+            family_id: "f1".to_string(),
+            user_id: "u1".to_string(),
+            device_id: "d1".to_string(),
+            used,
+            revoked,
+            created_at: DateTime::now(),
+            expires_at: DateTime::now(),
+        }
+    }
+
+    #[test]
+    fn test_reuse_detection() {
+        assert!(is_replay(&refresh_token(true, false)));
+        assert!(!is_replay(&refresh_token(false, false)));
+        assert!(!is_replay(&refresh_token(true, true)));
+        assert!(!is_replay(&refresh_token(false, true)));
+    }
+}
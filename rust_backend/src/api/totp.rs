@@ -0,0 +1,255 @@
+use crate::api::sessions::is_totp_verified;
+use crate::config::config::Config;
+use crate::db::collections::{
+    get_pending_totp_secrets_collection, get_totp_secrets_collection, get_users_collection,
+};
+use crate::models::device::Device;
+use crate::models::totp::{TotpCodeRequest, TotpSecret};
+use crate::utils::encryption::{decrypt_data, encrypt_data};
+use crate::utils::key_management::generate_totp_secret;
+use crate::utils::logger::log_event;
+use crate::utils::totp::{decode_secret, encode_secret, otpauth_uri, verify_code};
+use actix_web::{post, web, HttpResponse, Responder};
+use chrono::Utc;
+use log::error;
+use mongodb::{
+    bson::{doc, spec::BinarySubtype, Binary, DateTime},
+    Client,
+};
+use serde_json::json;
+
+/// Outcome of checking a TOTP code for a user
+#[derive(Debug, PartialEq)]
+pub enum TotpCheck {
+    Accepted,
+    Rejected,
+    NotEnrolled,
+}
+
+/// The confirmed secret a pending one becomes once a code from it was accepted at `step`
+fn promote(pending: TotpSecret, step: i64) -> TotpSecret {
+    TotpSecret { confirmed: true, last_used_step: step, ..pending }
+}
+
+/// Decrypts a stored TOTP seed with the server key.
+fn decrypt_secret(config: &Config, stored: &TotpSecret) -> Result<Vec<u8>, String> {
+    let encoded = decrypt_data(&stored.encrypted_secret.bytes, &config.server_encryption_key)
+        .map_err(|e| format!("Failed to decrypt TOTP secret: {}", e))?;
+    decode_secret(&encoded)
+}
+
+/// **Checks and consumes a TOTP code for a confirmed enrollment**
+/// A code is accepted once: its time step must be newer than the last accepted one.
+pub async fn consume_totp_code(
+    client: &Client,
+    config: &Config,
+    user_id: &str,
+    code: &str,
+) -> Result<TotpCheck, String> {
+    let db = client.database("valutx");
+    let collection = get_totp_secrets_collection(&db);
+
+    let stored = match collection
+        .find_one(doc! { "user_id": user_id, "confirmed": true })
+        .await
+        .map_err(|e| format!("Failed to load TOTP secret: {}", e))?
+    {
+        Some(stored) => stored,
+        None => return Ok(TotpCheck::NotEnrolled),
+    };
+
+    let secret = decrypt_secret(config, &stored)?;
+    let step = match verify_code(&secret, code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return Ok(TotpCheck::Rejected),
+    };
+
+    // Conditional update so two requests can't both spend the same step
+    let result = collection
+        .update_one(
+            doc! { "user_id": user_id, "confirmed": true, "last_used_step": { "$lt": step } },
+            doc! { "$set": { "last_used_step": step } },
+        )
+        .await
+        .map_err(|e| format!("Failed to record TOTP use: {}", e))?;
+
+    if result.matched_count == 0 {
+        return Ok(TotpCheck::Rejected);
+    }
+    Ok(TotpCheck::Accepted)
+}
+
+/// **Starts TOTP enrollment**
+/// Generates a new secret (replacing any unconfirmed one) and returns it with
+/// an otpauth:// URI. The secret is only active after `/totp/confirm`.
+/// Replacing an active authenticator needs a TOTP-verified session, e.g. one
+/// that redeemed a recovery code.
+#[post("/totp/enroll")]
+async fn enroll_totp(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    let collection = get_totp_secrets_collection(&db);
+
+    match collection
+        .find_one(doc! { "user_id": &claims.user_id, "confirmed": true })
+        .await
+    {
+        Ok(Some(_)) => match is_totp_verified(&client, &claims.jti).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return HttpResponse::Conflict()
+                    .json("❌ TOTP is already enabled; verify a code to replace it")
+            }
+            Err(e) => {
+                error!("{}", e);
+                return HttpResponse::InternalServerError().json("❌ Internal server error");
+            }
+        },
+        Ok(None) => {}
+        Err(e) => {
+            error!("Failed to load TOTP secret: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    }
+
+    let user = match get_users_collection(&db)
+        .find_one(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().json("❌ User not found"),
+        Err(e) => {
+            error!("Failed to load user: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let encoded_secret = encode_secret(&generate_totp_secret());
+    let encrypted = match encrypt_data(&encoded_secret, &config.server_encryption_key) {
+        Ok(encrypted) => encrypted,
+        Err(e) => {
+            error!("Failed to encrypt TOTP secret: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let pending = TotpSecret {
+        user_id: claims.user_id.clone(),
+        encrypted_secret: Binary { subtype: BinarySubtype::Generic, bytes: encrypted },
+        confirmed: false,
+        last_used_step: 0,
+        created_at: DateTime::now(),
+    };
+
+    // Pending secrets live in their own collection, so one can sit beside a confirmed one
+    if let Err(e) = get_pending_totp_secrets_collection(&db)
+        .replace_one(doc! { "user_id": &claims.user_id }, &pending)
+        .upsert(true)
+        .await
+    {
+        error!("Failed to store TOTP secret: {}", e);
+        return HttpResponse::InternalServerError().json("❌ Internal server error");
+    }
+
+    HttpResponse::Ok().json(json!({
+        "secret": encoded_secret,
+        "otpauth_uri": otpauth_uri("ValutX", &user.username, &encoded_secret),
+    }))
+}
+
+/// **Confirms TOTP enrollment with a first code from the authenticator app**
+#[post("/totp/confirm")]
+async fn confirm_totp(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Device>,
+    req: web::Json<TotpCodeRequest>,
+) -> impl Responder {
+    let db = client.database("valutx");
+    let pending_secrets = get_pending_totp_secrets_collection(&db);
+
+    let pending = match pending_secrets
+        .find_one(doc! { "user_id": &claims.user_id })
+        .await
+    {
+        Ok(Some(pending)) => pending,
+        Ok(None) => return HttpResponse::NotFound().json("❌ No pending TOTP enrollment"),
+        Err(e) => {
+            error!("Failed to load TOTP secret: {}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let secret = match decrypt_secret(&config, &pending) {
+        Ok(secret) => secret,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::InternalServerError().json("❌ Internal server error");
+        }
+    };
+
+    let step = match verify_code(&secret, &req.code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => {
+            log_event(&client, &claims.user_id, "totp_failed", "Invalid enrollment code").await;
+            return HttpResponse::Unauthorized().json("❌ Invalid TOTP code");
+        }
+    };
+
+    // The pending secret replaces any authenticator enrolled before it in one write
+    let enrolled_at = pending.created_at;
+    match get_totp_secrets_collection(&db)
+        .replace_one(doc! { "user_id": &claims.user_id }, promote(pending, step))
+        .upsert(true)
+        .await
+    {
+        Ok(_) => {
+            if let Err(e) = pending_secrets
+                .delete_one(doc! { "user_id": &claims.user_id, "created_at": enrolled_at })
+                .await {
+                error!("Failed to remove pending TOTP secret: {}", e);
+            }
+            log_event(
+                &client,
+                &claims.user_id,
+                "totp_enabled",
+                &format!("Confirmed from device {}", claims.device_id),
+            )
+            .await;
+            HttpResponse::Ok().json("✅ TOTP enabled")
+        }
+        Err(e) => {
+            error!("Failed to confirm TOTP: {}", e);
+            HttpResponse::InternalServerError().json("❌ Internal server error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reenroll_over_confirmed() {
+        let secret = |bytes: &[u8], confirmed| TotpSecret {
+            user_id: "user-1".to_string(),
+            encrypted_secret: Binary { subtype: BinarySubtype::Generic, bytes: bytes.to_vec() },
+            confirmed,
+            last_used_step: if confirmed { 100 } else { 0 },
+            created_at: DateTime::from_millis(0),
+        };
+        let current = secret(b"old", true);
+        let pending = secret(b"new", false);
+
+        // The pending secret takes over the user's single confirmed slot
+        let confirmed = promote(pending, 42);
+        assert!(confirmed.confirmed);
+        assert_eq!(confirmed.user_id, current.user_id);
+        assert_eq!(confirmed.encrypted_secret.bytes, b"new");
+        // Its step counter starts at the confirming code, not the old secret's
+        assert_eq!(confirmed.last_used_step, 42);
+    }
+}
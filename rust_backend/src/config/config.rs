@@ -1,22 +1,175 @@
 use dotenv::dotenv;
 use std::env;
 use std::error::Error;
+use url::Url;
+use crate::utils::fingerprint::FingerprintPolicy;
+use crate::utils::kdf::{MAX_KDF_PARALLELISM, MIN_KDF_ITERATIONS, MIN_KDF_MEMORY_KIB};
+use crate::utils::key_management::decode_hex;
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub mongo_uri: String,
     pub jwt_secret: String,
+    /// Lifetime of issued access tokens, in seconds.
+    pub jwt_ttl_secs: i64,
+    /// Lifetime of a refresh token, in seconds. Each rotation starts a new window,
+    /// so a device idle for longer than this has to log in again.
+    pub refresh_ttl_secs: i64,
+    /// WebAuthn relying party id: the registrable domain passkeys are bound to.
+    pub rp_id: String,
+    /// Human-readable relying party name shown by authenticators.
+    pub rp_name: String,
+    /// Origins allowed to perform WebAuthn ceremonies. The first one is primary.
+    pub rp_origins: Vec<Url>,
+    /// 32-byte key for encrypting server-held secrets at rest, such as TOTP seeds.
+    pub server_encryption_key: Vec<u8>,
+    /// How device fingerprint mismatches are handled.
+    pub fingerprint_policy: FingerprintPolicy,
+    /// Prefix lengths used to widen a device's source IP into its approved network
+    /// (default /24 and /64, so a DHCP renewal doesn't lock the device out).
+    pub fingerprint_ipv4_prefix: u8,
+    pub fingerprint_ipv6_prefix: u8,
+    /// Take the client IP from Forwarded/X-Forwarded-For. Only enable behind a trusted proxy.
+    pub trust_proxy_headers: bool,
+    /// Header in which a TLS-terminating proxy passes the client's TLS fingerprint.
+    pub tls_fingerprint_header: Option<String>,
+    /// A device unseen for longer than this must be approved again (default 14 days).
+    pub device_idle_secs: i64,
+    /// How often the background sweeper expires idle devices.
+    pub device_sweep_interval_secs: u64,
+    /// A device still waiting for approval after this long is discarded.
+    pub device_pending_secs: i64,
+    /// Revisions kept per record; older ones are pruned as new ones are written.
+    pub record_history_limit: u64,
+    /// How long one TOTP code unlocks the guarded endpoints for the session it was entered on.
+    pub totp_session_secs: i64,
+    /// How long a deleted record stays recoverable in the trash (default 30 days).
+    pub record_trash_retention_secs: i64,
+    /// How often the background task purges records past the trash retention.
+    pub record_purge_interval_secs: u64,
+    /// Argon2id settings given to new accounts for master key derivation.
+    /// Existing accounts keep theirs, since changing them changes the master key.
+    pub kdf_memory_kib: u32,
+    pub kdf_iterations: u32,
+    pub kdf_parallelism: u32,
 }
 
 impl Config {
     pub fn init() -> Result<Self, Box<dyn Error>> {
         // Load environment variables from a .env file if available.
-        dotenv()?;
+        dotenv().ok();
         
         let mongo_uri = env::var("MONGO_URI")?;
         let jwt_secret = env::var("JWT_SECRET")?;
+        let jwt_ttl_secs = env::var("JWT_TTL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(900);
+        let refresh_ttl_secs = env::var("REFRESH_TOKEN_TTL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(14 * 24 * 3600);
+        let rp_id = env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string());
+        let rp_name = env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "ValutX".to_string());
+        let rp_origins = env::var("WEBAUTHN_RP_ORIGINS")
+            .unwrap_or_else(|_| "http://localhost:8080".to_string())
+            .split(',')
+            .map(|origin| Url::parse(origin.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if rp_origins.is_empty() {
+            return Err("WEBAUTHN_RP_ORIGINS must list at least one origin".into());
+        }
+        let server_encryption_key = decode_hex(&env::var("SERVER_ENCRYPTION_KEY")?)?;
+        if server_encryption_key.len() != 32 {
+            return Err("SERVER_ENCRYPTION_KEY must be 64 hex characters (32 bytes)".into());
+        }
+        let fingerprint_policy = env::var("FINGERPRINT_POLICY")
+            .unwrap_or_else(|_| "strict".to_string())
+            .parse::<FingerprintPolicy>()?;
+        let fingerprint_ipv4_prefix = env::var("FINGERPRINT_IPV4_PREFIX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(24u8)
+            .min(32);
+        let fingerprint_ipv6_prefix = env::var("FINGERPRINT_IPV6_PREFIX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(64u8)
+            .min(128);
+        let trust_proxy_headers = env::var("TRUST_PROXY_HEADERS")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        let tls_fingerprint_header = env::var("TLS_FINGERPRINT_HEADER").ok();
+        let device_idle_secs = env::var("DEVICE_IDLE_TIMEOUT_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(14 * 24 * 3600);
+        let device_sweep_interval_secs = env::var("DEVICE_SWEEP_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600);
+        let device_pending_secs = env::var("DEVICE_PENDING_TIMEOUT_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15 * 60);
+        let totp_session_secs = env::var("TOTP_SESSION_WINDOW_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15 * 60);
+        let record_history_limit = env::var("RECORD_HISTORY_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(20u64)
+            .max(1);
+        let record_trash_retention_secs = env::var("RECORD_TRASH_RETENTION_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30 * 24 * 3600);
+        let record_purge_interval_secs = env::var("RECORD_PURGE_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600);
+        let kdf_memory_kib = env::var("KDF_MEMORY_KIB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(64 * 1024u32)
+            .max(MIN_KDF_MEMORY_KIB);
+        let kdf_iterations = env::var("KDF_ITERATIONS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3u32)
+            .max(MIN_KDF_ITERATIONS);
+        let kdf_parallelism = env::var("KDF_PARALLELISM")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4u32)
+            .clamp(1, MAX_KDF_PARALLELISM);
         
-        Ok(Self { mongo_uri, jwt_secret })
+        Ok(Self {
+            mongo_uri,
+            jwt_secret,
+            jwt_ttl_secs,
+            refresh_ttl_secs,
+            rp_id,
+            rp_name,
+            rp_origins,
+            server_encryption_key,
+            fingerprint_policy,
+            fingerprint_ipv4_prefix,
+            fingerprint_ipv6_prefix,
+            trust_proxy_headers,
+            tls_fingerprint_header,
+            device_idle_secs,
+            device_sweep_interval_secs,
+            device_pending_secs,
+            totp_session_secs,
+            record_history_limit,
+            record_trash_retention_secs,
+            record_purge_interval_secs,
+            kdf_memory_kib,
+            kdf_iterations,
+            kdf_parallelism,
+        })
     }
 }
 
@@ -29,9 +182,26 @@ mod tests {
         // Set dummy environment variables for testing.
         env::set_var("MONGO_URI", "mongodb://localhost:27017");
         env::set_var("JWT_SECRET", "mysecret");
+        env::set_var("SERVER_ENCRYPTION_KEY", "00".repeat(32));
 
         let config = Config::init().expect("Failed to initialize config");
         assert_eq!(config.mongo_uri, "mongodb://localhost:27017");
         assert_eq!(config.jwt_secret, "mysecret");
+        assert_eq!(config.jwt_ttl_secs, 900);
+        assert_eq!(config.refresh_ttl_secs, 14 * 24 * 3600);
+        assert_eq!(config.rp_id, "localhost");
+        assert_eq!(config.rp_origins[0].as_str(), "http://localhost:8080/");
+        assert_eq!(config.server_encryption_key, vec![0u8; 32]);
+        assert_eq!(config.fingerprint_policy, FingerprintPolicy::Strict);
+        assert_eq!(config.fingerprint_ipv4_prefix, 24);
+        assert!(!config.trust_proxy_headers);
+        assert_eq!(config.device_idle_secs, 14 * 24 * 3600);
+        assert_eq!(config.device_pending_secs, 15 * 60);
+        assert_eq!(config.totp_session_secs, 15 * 60);
+        assert_eq!(config.record_history_limit, 20);
+        assert_eq!(config.record_trash_retention_secs, 30 * 24 * 3600);
+        assert_eq!(config.kdf_memory_kib, 64 * 1024);
+        assert_eq!(config.kdf_iterations, 3);
+        assert_eq!(config.kdf_parallelism, 4);
     }
 }
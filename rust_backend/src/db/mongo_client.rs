@@ -1,8 +1,7 @@
-use mongodb::{Client, Database};
+use mongodb::Client;
 
-pub async fn get_database(uri: &str) -> Database {
-    let client = Client::with_uri_str(uri)
+pub async fn get_client(uri: &str) -> Client {
+    Client::with_uri_str(uri)
         .await
-        .expect("Failed to connect to MongoDB");
-    client.database("valutx")
+        .expect("Failed to connect to MongoDB")
 }
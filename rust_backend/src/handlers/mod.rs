@@ -1,3 +0,0 @@
-pub mod auth_handler;
-pub mod records_handler;
-pub mod register_handler;
\ No newline at end of file
@@ -1,31 +1,58 @@
-use actix_web::{App, HttpServer};
+use actix_web::{web, App, HttpServer};
 use dotenv::dotenv;
 use std::env;
-use env_logger;
-use log::info;
+use log::{error, info};
 
 mod api;
 mod config;
 mod db;
-mod handlers;
 mod models;
 mod utils;
 mod middleware;
+mod tasks;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Initialize the logger
     env_logger::init();
     info!("Application started");
-    
+
     dotenv().ok();
     let server_address =
         env::var("SERVER_ADDRESS").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
 
+    let config = config::config::Config::init().expect("Failed to load configuration");
+    let client = db::mongo_client::get_client(&config.mongo_uri).await;
+    // Without its unique indexes the server would accept duplicate accounts and keys
+    db::collections::ensure_indexes(&client.database("valutx"))
+        .await
+        .expect("Failed to create database indexes");
+    if let Err(e) = db::collections::backfill_records(&client.database("valutx")).await {
+        error!("{}", e);
+    }
+    if let Err(e) = utils::kdf::record_kdf_policies(&client.database("valutx"), &config).await {
+        error!("{}", e);
+    }
+    tasks::device_expiry::spawn(client.clone(), config.clone());
+    tasks::trash_purge::spawn(client.clone(), config.clone());
+
     println!("Starting server on {}", server_address);
 
-    HttpServer::new(|| App::new().configure(api::init_routes))
-        .bind(server_address)?
-        .run()
-        .await
+    let webauthn = api::authentication::build_webauthn(&config)
+        .expect("Failed to configure WebAuthn relying party");
+
+    let config = web::Data::new(config);
+    let client = web::Data::new(client);
+    let webauthn = web::Data::new(webauthn);
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(config.clone())
+            .app_data(client.clone())
+            .app_data(webauthn.clone())
+            .configure(api::init_routes)
+    })
+    .bind(server_address)?
+    .run()
+    .await
 }
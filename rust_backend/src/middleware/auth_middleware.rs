@@ -1,74 +1,190 @@
-use crate::db::mongo_client::get_database;
-use crate::models::device::Device;
-use actix_web::{dev::ServiceRequest, Error, HttpMessage};
+use crate::api::devices::{check_device_access, touch_device, DeviceAccess};
+use crate::config::config::Config;
+use crate::db::collections::{get_devices_collection, get_sessions_collection};
+use crate::models::device::{Device, TrustedDevice};
+use crate::utils::fingerprint::{compare, network_for, observe, to_stored, FingerprintMismatch};
+use crate::utils::hashing::hash_token;
+use crate::utils::logger::log_event;
+use crate::utils::token::decode_token;
+use actix_web::{dev::ServiceRequest, web, Error, HttpMessage};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use mongodb::{bson::doc, Collection};
-use std::env;
+use log::{error, warn};
+use mongodb::{bson::doc, Client};
 
 pub async fn validate_request(
     req: ServiceRequest,
     credentials: BearerAuth,
 ) -> Result<ServiceRequest, (Error, ServiceRequest)> {
-    let token = credentials.token();
-    let jwt_secret = match env::var("JWT_SECRET") {
-        Ok(secret) => secret,
-        Err(_) => {
-            return Err((
-                actix_web::error::ErrorInternalServerError("JWT_SECRET not set"),
-                req,
-            ));
+    match authorize(&req, credentials.token()).await {
+        Ok(claims) => {
+            req.extensions_mut().insert(claims);
+            Ok(req)
         }
+        Err(e) => Err((e, req)),
+    }
+}
+
+/// Checks the token, its session, the device approval and the device fingerprint.
+async fn authorize(req: &ServiceRequest, token: &str) -> Result<Device, Error> {
+    let (config, client) = match (
+        req.app_data::<web::Data<Config>>(),
+        req.app_data::<web::Data<Client>>(),
+    ) {
+        (Some(config), Some(client)) => (config.clone(), client.clone()),
+        _ => return Err(actix_web::error::ErrorInternalServerError("Server not configured")),
     };
 
     // Decode JWT token
-    let decoded_token = decode::<Device>(
-        token,
-        &DecodingKey::from_secret(jwt_secret.as_bytes()),
-        &Validation::new(Algorithm::HS256),
-    );
+    let claims = decode_token(token, &config.jwt_secret)
+        .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid token"))?;
+
+    let db = client.database("valutx");
 
-    let token_data = match decoded_token {
-        Ok(data) => data,
-        Err(_) => {
-            return Err((actix_web::error::ErrorUnauthorized("Invalid token"), req));
+    // Reject tokens whose session was logged out or revoked
+    match get_sessions_collection(&db)
+        .find_one(doc! { "jti": &claims.jti, "revoked": false })
+        .await
+    {
+        Ok(Some(_session)) => {}
+        Ok(None) => return Err(actix_web::error::ErrorUnauthorized("Session revoked")),
+        Err(_) => return Err(actix_web::error::ErrorInternalServerError("Database error")),
+    }
+
+    // Validate the device from the database
+    let device = match check_device_access(&client, &config, &claims.user_id, &claims.device_id).await {
+        Ok(DeviceAccess::Active(device)) => device,
+        Ok(DeviceAccess::ReapprovalRequired) => {
+            return Err(actix_web::error::ErrorForbidden("Device reapproval required"));
+        }
+        Ok(DeviceAccess::PendingApproval) => {
+            return Err(actix_web::error::ErrorForbidden("Device awaiting approval"));
+        }
+        Ok(DeviceAccess::Unknown) => {
+            return Err(actix_web::error::ErrorUnauthorized("Unauthorized device"));
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Database error"));
         }
     };
 
-    // Connect to MongoDB
-    let mongo_uri = match env::var("MONGO_URI") {
-        Ok(uri) => uri,
-        Err(_) => {
-            return Err((
-                actix_web::error::ErrorInternalServerError("MONGO_URI not set"),
-                req,
-            ));
+    check_fingerprint(req, &config, &client, &claims, &device).await?;
+
+    if let Err(e) = touch_device(&client, &claims.user_id, &claims.device_id).await {
+        error!("{}", e);
+    }
+
+    Ok(claims)
+}
+
+/// Compares the request's fingerprint with the one stored at approval.
+/// Devices approved without a fingerprint are bound to their first one, which
+/// is then checked like any other.
+/// A mismatch is logged once per distinct observed fingerprint, not per request.
+async fn check_fingerprint(
+    req: &ServiceRequest,
+    config: &Config,
+    client: &Client,
+    claims: &Device,
+    device: &TrustedDevice,
+) -> Result<(), Error> {
+    let observed = observe(req.request(), config);
+
+    let bound;
+    let stored = match &device.fingerprint {
+        Some(stored) => stored,
+        None => {
+            let Some(fingerprint) = to_stored(&observed, config) else {
+                // Nothing to bind to
+                return if config.fingerprint_policy.denies(&[FingerprintMismatch::HardwareMissing]) {
+                    Err(actix_web::error::ErrorUnauthorized("Device fingerprint required"))
+                } else {
+                    Ok(())
+                };
+            };
+            let db = client.database("valutx");
+            let encoded = mongodb::bson::to_bson(&fingerprint)
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+            get_devices_collection(&db)
+                .update_one(
+                    doc! {
+                        "user_id": &claims.user_id,
+                        "device_id": &claims.device_id,
+                        "fingerprint": null,
+                    },
+                    doc! { "$set": { "fingerprint": encoded } },
+                )
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+            log_event(
+                client,
+                &claims.user_id,
+                "fingerprint_bound",
+                &format!("Device {}", claims.device_id),
+            )
+            .await;
+            bound = fingerprint;
+            &bound
         }
     };
 
-    let db = get_database(&mongo_uri).await;
-    let users_collection: Collection<mongodb::bson::Document> = db.collection("users");
+    let db = client.database("valutx");
+    let device_filter = doc! { "user_id": &claims.user_id, "device_id": &claims.device_id };
+    let mismatches = compare(stored, &observed);
+    if mismatches.is_empty() {
+        if device.last_mismatch.is_some() {
+            get_devices_collection(&db)
+                .update_one(device_filter, doc! { "$unset": { "last_mismatch": "" } })
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+        }
+        return Ok(());
+    }
+
+    let denied = config.fingerprint_policy.denies(&mismatches);
+    let mismatch_key = hash_token(&format!(
+        "{:?}|{}|{}|{}",
+        mismatches,
+        observed.hardware_hash.as_deref().unwrap_or_default(),
+        observed.ip.map(|ip| network_for(ip, config)).unwrap_or_default(),
+        observed.tls_fingerprint.as_deref().unwrap_or_default(),
+    ));
+    let mut unlogged = device_filter;
+    unlogged.insert("last_mismatch", doc! { "$ne": &mismatch_key });
+    let first_seen = get_devices_collection(&db)
+        .update_one(unlogged, doc! { "$set": { "last_mismatch": &mismatch_key } })
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+        .modified_count
+        == 1;
+    if !first_seen {
+        return if denied {
+            Err(actix_web::error::ErrorUnauthorized("Device fingerprint mismatch"))
+        } else {
+            Ok(())
+        };
+    }
 
-    // Validate the user from the database
-    let user_result = users_collection
-        .find_one(
-            doc! { "user_id": &token_data.claims.user_id, "device_id": &token_data.claims.device_id },
-            None,
-        )
-        .await;
+    warn!(
+        "Fingerprint mismatch {:?} for user '{}' on device '{}' (denied: {}).",
+        mismatches, claims.user_id, claims.device_id, denied
+    );
+    log_event(
+        client,
+        &claims.user_id,
+        "fingerprint_mismatch",
+        &format!(
+            "Device {}: {:?} from {}{}",
+            claims.device_id,
+            mismatches,
+            observed.ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string()),
+            if denied { ", access denied" } else { "" }
+        ),
+    )
+    .await;
 
-    match user_result {
-        Ok(Some(_user)) => {
-            req.extensions_mut().insert(token_data.claims);
-            Ok(req)
-        }
-        Ok(None) => Err((
-            actix_web::error::ErrorUnauthorized("Unauthorized device"),
-            req,
-        )),
-        Err(_) => Err((
-            actix_web::error::ErrorInternalServerError("Database error"),
-            req,
-        )),
+    if denied {
+        return Err(actix_web::error::ErrorUnauthorized("Device fingerprint mismatch"));
     }
+    Ok(())
 }
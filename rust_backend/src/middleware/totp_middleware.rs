@@ -0,0 +1,82 @@
+use crate::api::sessions::{is_totp_verified, mark_totp_verified};
+use crate::api::totp::{consume_totp_code, TotpCheck};
+use crate::config::config::Config;
+use crate::models::device::Device;
+use crate::utils::logger::log_event;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error, HttpMessage,
+};
+use log::error;
+use mongodb::{bson::DateTime, Client};
+
+/// Header carrying the current TOTP code on sync requests
+pub const TOTP_HEADER: &str = "X-TOTP-Code";
+
+/// **Requires a verified TOTP code for the session**
+/// A code is spent once; an accepted one covers the session's requests for
+/// `totp_session_secs`, after which a new code is needed.
+/// Must run inside the bearer-auth scope, which provides the `Device` claims.
+pub async fn require_totp(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let claims = req
+        .extensions()
+        .get::<Device>()
+        .cloned()
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing session"))?;
+
+    let (config, client) = match (
+        req.app_data::<web::Data<Config>>(),
+        req.app_data::<web::Data<Client>>(),
+    ) {
+        (Some(config), Some(client)) => (config.clone(), client.clone()),
+        _ => return Err(actix_web::error::ErrorInternalServerError("Server not configured")),
+    };
+
+    match is_totp_verified(&client, &claims.jti).await {
+        Ok(true) => return next.call(req).await,
+        Ok(false) => {}
+        Err(e) => {
+            error!("{}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Internal server error"));
+        }
+    }
+
+    let code = req
+        .headers()
+        .get(TOTP_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("TOTP code required"))?;
+
+    match consume_totp_code(&client, &config, &claims.user_id, &code).await {
+        Ok(TotpCheck::Accepted) => {
+            let until = DateTime::from_millis(
+                DateTime::now().timestamp_millis() + config.totp_session_secs * 1000,
+            );
+            if let Err(e) = mark_totp_verified(&client, &claims.jti, until).await {
+                error!("{}", e);
+            }
+            next.call(req).await
+        }
+        Ok(TotpCheck::Rejected) => {
+            log_event(
+                &client,
+                &claims.user_id,
+                "totp_failed",
+                &format!("Rejected sync code from device {}", claims.device_id),
+            )
+            .await;
+            Err(actix_web::error::ErrorUnauthorized("Invalid TOTP code"))
+        }
+        Ok(TotpCheck::NotEnrolled) => Err(actix_web::error::ErrorForbidden("TOTP enrollment required")),
+        Err(e) => {
+            error!("TOTP check failed: {}", e);
+            Err(actix_web::error::ErrorInternalServerError("Internal server error"))
+        }
+    }
+}
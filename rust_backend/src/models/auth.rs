@@ -1,21 +1,57 @@
+use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
+use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthRequest {
     pub username: String,
+    /// Account password. Never the master password, which stays on the client.
     pub password: String,
     pub device_id: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct WebAuthnAuthRequest {
-    pub user_id: String,
-    pub challenge: String,
+pub struct WebAuthnVerifyRequest {
+    pub passkey_session_id: String,
+    pub credential: RegisterPublicKeyCredential,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct WebAuthnVerifyRequest {
-    pub user_id: String,
-    pub credential_id: String,
+pub struct PasskeyLoginStartRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyLoginFinishRequest {
     pub passkey_session_id: String,
+    pub device_id: String,
+    pub credential: PublicKeyCredential,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Tokens handed to a client after login or refresh
+#[derive(Debug, Serialize)]
+pub struct SessionTokens {
+    pub token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub refresh_token: String,
+}
+
+/// Stored refresh token. Only the SHA-256 hash of the token is persisted;
+/// every rotation stays in the same `family_id` so a replay can revoke the chain.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshToken {
+    pub token_hash: String,
+    pub family_id: String,
+    pub user_id: String,
+    pub device_id: String,
+    pub used: bool,
+    pub revoked: bool,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
 }
@@ -1,8 +1,73 @@
+use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Session token claims; one token is bound to one user and one device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Device {
     pub device_id: String,
+    #[serde(rename = "exp")]
     pub expiration: usize,        // Expiry
     pub user_id: String,
+    pub jti: String,              // Unique token id
+}
+
+/// Fingerprint recorded when a device is approved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceFingerprint {
+    /// Client-reported hash of hardware identifiers; `None` if the client sent none
+    #[serde(default)]
+    pub hardware_hash: Option<String>,
+    /// Source network in CIDR form
+    pub ip_network: Option<String>,
+    /// TLS client fingerprint (JA3-style hash) when a proxy supplies one
+    pub tls_fingerprint: Option<String>,
+}
+
+/// Lifecycle state of a device
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceStatus {
+    /// Registered by the device itself; waiting for a trusted device to approve it
+    Pending,
+    Approved,
+    /// Idle past the inactivity window; must be approved again
+    Expired,
+}
+
+/// A device approved for an account, stored in the `devices` collection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrustedDevice {
+    pub user_id: String,
+    pub device_id: String,
+    /// Friendly name set by the user
+    #[serde(default)]
+    pub name: Option<String>,
+    pub status: DeviceStatus,
+    pub fingerprint: Option<DeviceFingerprint>,
+    pub approved_at: Option<DateTime>,
+    pub last_seen_at: Option<DateTime>,
+    /// When the device asked to be approved
+    #[serde(default)]
+    pub requested_at: Option<DateTime>,
+    /// Pending devices are discarded (TTL index) once this passes; unset on approval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_expires_at: Option<DateTime>,
+    /// Hash of the last logged fingerprint mismatch, so a repeat isn't logged on every request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_mismatch: Option<String>,
+}
+
+/// Request body for renaming a device
+#[derive(Debug, Deserialize)]
+pub struct DeviceRenameRequest {
+    pub name: String,
+}
+
+/// Submitted by a new device, which has no session yet, to ask for approval
+#[derive(Debug, Deserialize)]
+pub struct DeviceRegistrationRequest {
+    pub username: String,
+    pub device_id: String,
+    /// Pairing code issued by one of the account's trusted devices
+    pub pairing_code: String,
 }
@@ -1,18 +1,31 @@
-use serde::Deserialize;
+use crate::models::key::{KdfParams, WrappedKey};
+use crate::models::record::{RecordMetadata, RecordType};
+use serde::{Deserialize, Serialize};
 
-pub fn gen_random(len: usize) -> Vec<u8> {
-    use rand::Rng;
-    let mut rng = rand::thread_rng();
-    (0..len).map(|_| rng.gen()).collect()
-}
+/// Backup layout version, bumped on incompatible changes
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
 
-#[derive(Deserialize)]
-pub struct BackupRequest {
-    pub encryption_key: String,
+/// A user's vault as exported by `/secure/backup`. Holds only ciphertext and
+/// wrapped keys, so it is safe at rest and opens only with the master password.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultBackup {
+    pub format_version: u32,
+    /// Parameters the master key is derived with, so the backup opens on its own
+    pub kdf: KdfParams,
+    /// The vault key wrapped by the master key
+    pub wrapped_vault_key: WrappedKey,
+    pub records: Vec<BackupRecord>,
 }
 
-#[derive(Deserialize)]
-pub struct RestoreRequest {
-    pub encryption_key: String,
-    pub backup_data: Vec<u8>,
-}
\ No newline at end of file
+/// One live record in a backup; folders and tags are not included
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub id: String,
+    #[serde(default)]
+    pub record_type: RecordType,
+    pub title: String,
+    #[serde(default)]
+    pub metadata: RecordMetadata,
+    pub encrypted_data: String,
+    pub wrapped_item_key: Option<WrappedKey>,
+}
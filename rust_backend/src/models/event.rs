@@ -0,0 +1,32 @@
+use serde::Serialize;
+
+/// Something another device of the same user should react to
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VaultEvent {
+    /// Records changed; fetch them with `/secure/sync?since=`.
+    /// `device_id` is the device that made the change, `None` for server jobs.
+    VaultChanged { revision: i64, device_id: Option<String> },
+    /// A new device registered with a pairing code and awaits approval
+    DevicePending { device_id: String },
+    DeviceApproved { device_id: String },
+    DeviceRevoked { device_id: String },
+    /// An entry was written to the security log
+    Security { event_type: String, details: String },
+    /// The stream fell behind and dropped events; do a delta sync to catch up
+    Resync,
+}
+
+impl VaultEvent {
+    /// SSE event name, same as the serialized `type`
+    pub fn name(&self) -> &'static str {
+        match self {
+            VaultEvent::VaultChanged { .. } => "vault_changed",
+            VaultEvent::DevicePending { .. } => "device_pending",
+            VaultEvent::DeviceApproved { .. } => "device_approved",
+            VaultEvent::DeviceRevoked { .. } => "device_revoked",
+            VaultEvent::Security { .. } => "security",
+            VaultEvent::Resync => "resync",
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// A folder for organizing records, stored in the `folders` collection.
+/// A record is in at most one folder.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Folder {
+    pub folder_id: String,
+    pub user_id: String,
+    /// Encrypted by the client like record data
+    pub encrypted_name: String,
+    pub created_at: DateTime,
+}
+
+/// A tag, stored in the `tags` collection. Records can carry any number of tags.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tag {
+    pub tag_id: String,
+    pub user_id: String,
+    /// Encrypted by the client like record data
+    pub encrypted_name: String,
+    pub created_at: DateTime,
+}
+
+/// Body for creating or renaming a folder or tag
+#[derive(Debug, Deserialize)]
+pub struct LabelRequest {
+    pub encrypted_name: String,
+}
+
+/// Options for deleting a folder
+#[derive(Debug, Deserialize)]
+pub struct FolderDeleteQuery {
+    /// Move the folder's records to the trash instead of leaving them unfiled
+    #[serde(default)]
+    pub trash_records: bool,
+}
@@ -0,0 +1,72 @@
+//! Key hierarchy. All wrapping happens on the client; the server only stores wrapped keys.
+//!
+//! - **Master key**: derived on the client from the master password with Argon2id,
+//!   using the user's `KdfParams`. Never sent. The master password is not the
+//!   account password: that one reaches the server on every login, so a key
+//!   derived from it would be one the server could derive too.
+//! - **Vault key**: random 256-bit key per user, wrapped by the master key (`VaultKey`).
+//! - **Item key**: random 256-bit key per record, wrapped by the vault key
+//!   (`Record::wrapped_item_key`). It encrypts the record's `encrypted_data`.
+//!
+//! Changing the master password only rewraps the vault key; records are untouched.
+
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// Argon2id settings for deriving a user's master key, stored on the user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Always `argon2id`; named so clients can refuse anything else
+    pub algorithm: String,
+    /// 16 random bytes, hex encoded
+    pub salt: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+/// A set of Argon2id costs that has been issued to accounts, stored in `kdf_policies`.
+/// Decoy parameters are drawn from these, so unknown usernames look like
+/// accounts of any age rather than only new ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KdfPolicy {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    pub created_at: DateTime,
+}
+
+/// Body for looking up a user's KDF parameters before login
+#[derive(Debug, Deserialize)]
+pub struct KdfRequest {
+    pub username: String,
+}
+
+/// A 256-bit key encrypted with AES-256-GCM under its parent key, hex encoded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WrappedKey {
+    /// 96-bit GCM nonce
+    pub nonce: String,
+    /// Encrypted key followed by the 128-bit GCM tag
+    pub ciphertext: String,
+}
+
+/// The user's vault key wrapped by their master key, stored in `vault_keys`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultKey {
+    pub user_id: String,
+    pub wrapped_key: WrappedKey,
+    /// Bumped each time the vault key is rewrapped under a new master key
+    pub key_version: i64,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+/// Body for storing the first wrapped vault key or rewrapping it
+#[derive(Debug, Deserialize)]
+pub struct VaultKeyRequest {
+    pub wrapped_key: WrappedKey,
+    /// Version being replaced when rewrapping, so two devices can't both rewrap
+    #[serde(default)]
+    pub replaces_version: Option<i64>,
+}
@@ -1,8 +1,11 @@
+use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LogEntry {
-    timestamp: String,
-    event_type: String,
-    details: String,
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub timestamp: String,
+    pub event_type: String,
+    pub details: String,
 }
@@ -1,6 +1,16 @@
 pub mod auth;
 pub mod device;
 pub mod encryption;
+pub mod event;
+pub mod folder;
+pub mod key;
 pub mod log;
+pub mod pagination;
+pub mod pairing;
 pub mod record;
-pub mod user;
\ No newline at end of file
+pub mod recovery;
+pub mod session;
+pub mod sync;
+pub mod totp;
+pub mod user;
+pub mod webauthn;
\ No newline at end of file
@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+/// Query parameters shared by paginated list endpoints: `?limit=&after=&sort=`
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    /// Items per page; capped by the server
+    pub limit: Option<i64>,
+    /// `next_cursor` from the previous page
+    pub after: Option<String>,
+    /// Field to sort by, `-` prefixed for descending, e.g. `-updated_at`
+    pub sort: Option<String>,
+}
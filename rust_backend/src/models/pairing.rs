@@ -0,0 +1,29 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// Progress of a pairing code
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairingStatus {
+    /// Issued by a trusted device, waiting for the new device to submit it
+    Open,
+    /// Spent by a new device, which is now pending approval
+    Claimed,
+}
+
+/// A short-lived pairing code issued by a trusted device, stored in `device_pairings`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DevicePairing {
+    pub pairing_id: String,
+    pub user_id: String,
+    /// SHA-256 of the normalized code; the code itself is only shown once
+    pub code_hash: String,
+    /// Trusted device that issued the code
+    pub created_by: String,
+    pub status: PairingStatus,
+    /// Codes submitted against this pairing, right or wrong
+    pub attempts: i32,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+}
+
@@ -1,9 +1,138 @@
+use crate::models::key::WrappedKey;
+use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
 
+/// Kind of vault entry; decides which plaintext metadata a record may carry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordType {
+    Login,
+    #[default]
+    SecureNote,
+    Card,
+    Identity,
+    SshKey,
+}
+
+impl RecordType {
+    /// Name as stored and as used in query filters
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordType::Login => "login",
+            RecordType::SecureNote => "secure_note",
+            RecordType::Card => "card",
+            RecordType::Identity => "identity",
+            RecordType::SshKey => "ssh_key",
+        }
+    }
+}
+
+/// Unencrypted metadata used for matching, filtering and display.
+/// Never holds secrets; which fields are allowed depends on the `RecordType`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecordMetadata {
+    /// Login: sites the credential is used on, for autofill matching
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uris: Vec<String>,
+    /// Card: network such as `visa`, for the card icon
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card_brand: Option<String>,
+    /// SSH key: algorithm such as `ed25519`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key_type: Option<String>,
+    /// SSH key: public key fingerprint, e.g. `SHA256:...`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_fingerprint: Option<String>,
+}
+
+/// A vault entry, stored in the `records` collection.
+/// `encrypted_data` is ciphertext produced by the client under the record's own item key;
+/// the server never sees plaintext.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Record {
     #[serde(rename = "_id")]
     pub id: String,
+    /// Owner; every query is scoped by it
+    pub user_id: String,
+    #[serde(default)]
+    pub record_type: RecordType,
     pub title: String,
+    #[serde(default)]
+    pub metadata: RecordMetadata,
     pub encrypted_data: String,
+    /// Item key for `encrypted_data`, wrapped by the user's vault key
+    #[serde(default)]
+    pub wrapped_item_key: Option<WrappedKey>,
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    #[serde(default)]
+    pub tag_ids: Vec<String>,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+    /// Set while the record is in the trash
+    #[serde(default)]
+    pub deleted_at: Option<DateTime>,
+    /// Sync revision of the last change, from the owner's counter
+    #[serde(default)]
+    pub revision: i64,
+    /// Sync revision the record was created at
+    #[serde(default)]
+    pub created_revision: i64,
+    /// Bumped on every change; sent as the ETag and expected back in `If-Match`
+    #[serde(default)]
+    pub version: i64,
+}
+
+/// A version of a record as it was written, stored in the append-only `record_revisions` collection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordRevision {
+    pub revision_id: String,
+    pub record_id: String,
+    pub user_id: String,
+    #[serde(default)]
+    pub record_type: RecordType,
+    pub title: String,
+    #[serde(default)]
+    pub metadata: RecordMetadata,
+    pub encrypted_data: String,
+    #[serde(default)]
+    pub wrapped_item_key: Option<WrappedKey>,
+    /// Device that made the change
+    pub device_id: String,
+    /// Record version this revision was written as
+    #[serde(default)]
+    pub version: i64,
+    /// A write rejected because the record had changed since the device read it.
+    /// Kept so the client can merge it with the current version.
+    #[serde(default)]
+    pub conflict: bool,
+    pub created_at: DateTime,
+}
+
+/// Body for creating or updating a record; ids and timestamps are set by the server
+#[derive(Debug, Deserialize)]
+pub struct RecordRequest {
+    #[serde(default)]
+    pub record_type: RecordType,
+    pub title: String,
+    #[serde(default)]
+    pub metadata: RecordMetadata,
+    pub encrypted_data: String,
+    #[serde(default)]
+    pub wrapped_item_key: Option<WrappedKey>,
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    #[serde(default)]
+    pub tag_ids: Vec<String>,
+}
+
+/// Filters accepted by the records listing
+#[derive(Debug, Deserialize)]
+pub struct RecordQuery {
+    #[serde(rename = "type")]
+    pub record_type: Option<RecordType>,
+    /// Only records in this folder
+    pub folder: Option<String>,
+    /// Only records carrying this tag
+    pub tag: Option<String>,
 }
@@ -0,0 +1,28 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// A single-use recovery code; only its Argon2 hash is stored
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCode {
+    pub hash: String,
+    pub used_at: Option<DateTime>,
+}
+
+/// The current set of recovery codes for a user. Regenerating replaces the whole set.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodeSet {
+    pub user_id: String,
+    pub codes: Vec<RecoveryCode>,
+    pub created_at: DateTime,
+    /// Redeem attempts since the last success or lockout
+    #[serde(default)]
+    pub failed_attempts: i32,
+    /// Redeeming is refused until then after too many failures
+    #[serde(default)]
+    pub locked_until: Option<DateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodeRequest {
+    pub code: String,
+}
@@ -0,0 +1,19 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// A logged-in session. `session_id` is shared with the refresh token family,
+/// and `jti` tracks the access token currently issued for it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub session_id: String,
+    pub jti: String,
+    pub user_id: String,
+    pub device_id: String,
+    pub revoked: bool,
+    pub created_at: DateTime,
+    pub last_refreshed_at: DateTime,
+    pub expires_at: DateTime,
+    /// Until when a TOTP code entered on this session covers guarded requests
+    #[serde(default)]
+    pub totp_verified_until: Option<DateTime>,
+}
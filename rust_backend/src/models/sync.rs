@@ -0,0 +1,44 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// Per-user change counter, stored in `sync_counters`. Every record write takes the next value.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncCounter {
+    pub user_id: String,
+    pub revision: i64,
+    /// Revisions taken whose writes haven't been confirmed yet
+    #[serde(default)]
+    pub pending: Vec<PendingRevision>,
+}
+
+/// A revision handed out by the counter, held until its write lands
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingRevision {
+    pub revision: i64,
+    pub started_at: DateTime,
+}
+
+/// Marks a record that was purged, so devices that last synced before the purge learn of it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordTombstone {
+    pub user_id: String,
+    pub record_id: String,
+    pub revision: i64,
+    pub deleted_at: DateTime,
+}
+
+/// The revision each device last synced to, stored in `sync_states`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncState {
+    pub user_id: String,
+    pub device_id: String,
+    pub revision: i64,
+    pub synced_at: DateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncQuery {
+    /// Revision returned by the previous sync; omit for a full sync
+    #[serde(default)]
+    pub since: i64,
+}
@@ -0,0 +1,18 @@
+use mongodb::bson::{Binary, DateTime};
+use serde::{Deserialize, Serialize};
+
+/// A user's TOTP seed, encrypted with the server key.
+/// `last_used_step` is the newest accepted time step, so a code can't be replayed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpSecret {
+    pub user_id: String,
+    pub encrypted_secret: Binary,
+    pub confirmed: bool,
+    pub last_used_step: i64,
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
@@ -1,8 +1,13 @@
+use crate::models::key::KdfParams;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
+    pub user_id: String,
     pub username: String,
     pub password_hash: String,
     pub device_id: String,
+    /// Master key derivation settings; accounts created before they existed get them on first lookup
+    #[serde(default)]
+    pub kdf: Option<KdfParams>,
 }
@@ -0,0 +1,23 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// Server-side state of an in-progress WebAuthn ceremony.
+/// `state` holds the serialized webauthn-rs state and never leaves the server.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebAuthnCeremony {
+    pub passkey_session_id: String,
+    pub user_id: String,
+    pub kind: String,
+    pub state: String,
+    pub expires_at: DateTime,
+}
+
+/// A passkey registered to a user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredPasskey {
+    pub user_id: String,
+    pub credential_id: String,
+    pub passkey: String,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
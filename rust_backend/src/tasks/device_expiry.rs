@@ -0,0 +1,59 @@
+use crate::api::devices::expire_device;
+use crate::config::config::Config;
+use crate::db::collections::get_devices_collection;
+use futures::TryStreamExt;
+use log::{error, info};
+use mongodb::{
+    bson::{doc, DateTime},
+    Client,
+};
+use std::time::Duration;
+
+/// Starts the background sweep that expires devices idle past the configured window.
+pub fn spawn(client: Client, config: Config) {
+    tokio::spawn(async move {
+        let period = Duration::from_secs(config.device_sweep_interval_secs.max(1));
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match sweep(&client, &config).await {
+                Ok(0) => {}
+                Ok(expired) => info!("Device sweep expired {} idle device(s).", expired),
+                Err(e) => error!("{}", e),
+            }
+        }
+    });
+}
+
+/// **Expires every approved device not seen within the inactivity window**
+/// Devices that were never used count from their approval time. Returns how many
+/// were expired; devices that fail are logged and skipped.
+pub async fn sweep(client: &Client, config: &Config) -> Result<usize, String> {
+    let cutoff = DateTime::from_millis(
+        DateTime::now().timestamp_millis() - config.device_idle_secs * 1000,
+    );
+    let db = client.database("valutx");
+    let stale: Vec<_> = get_devices_collection(&db)
+        .find(doc! {
+            "status": "approved",
+            "$or": [
+                { "last_seen_at": { "$lt": cutoff } },
+                { "last_seen_at": null, "approved_at": { "$lt": cutoff } },
+            ],
+        })
+        .await
+        .map_err(|e| format!("Failed to find idle devices: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to read idle devices: {}", e))?;
+
+    // A device that fails to expire is retried on the next sweep
+    let mut expired = 0;
+    for device in &stale {
+        match expire_device(client, &device.user_id, &device.device_id).await {
+            Ok(()) => expired += 1,
+            Err(e) => error!("{}", e),
+        }
+    }
+    Ok(expired)
+}
@@ -0,0 +1,2 @@
+pub mod device_expiry;
+pub mod trash_purge;
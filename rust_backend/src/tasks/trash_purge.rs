@@ -0,0 +1,50 @@
+use crate::api::records::{purge_cutoff, purge_record};
+use crate::config::config::Config;
+use crate::db::collections::get_records_collection;
+use futures::TryStreamExt;
+use log::{error, info};
+use mongodb::{
+    bson::{doc, DateTime},
+    Client,
+};
+use std::time::Duration;
+
+/// Starts the background task that purges records left in the trash past the retention period.
+pub fn spawn(client: Client, config: Config) {
+    tokio::spawn(async move {
+        let period = Duration::from_secs(config.record_purge_interval_secs.max(1));
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match purge(&client, &config).await {
+                Ok(0) => {}
+                Ok(purged) => info!("Trash purge deleted {} record(s).", purged),
+                Err(e) => error!("{}", e),
+            }
+        }
+    });
+}
+
+/// **Permanently deletes every record trashed before the retention cutoff**
+/// Returns how many were purged; records that fail are logged and skipped.
+pub async fn purge(client: &Client, config: &Config) -> Result<usize, String> {
+    let cutoff = purge_cutoff(DateTime::now(), config.record_trash_retention_secs);
+    let db = client.database("valutx");
+    let expired: Vec<_> = get_records_collection(&db)
+        .find(doc! { "deleted_at": { "$ne": null, "$lt": cutoff } })
+        .await
+        .map_err(|e| format!("Failed to find trashed records: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to read trashed records: {}", e))?;
+
+    // One failed record is retried on the next sweep and doesn't hold up the rest
+    let mut purged = 0;
+    for record in &expired {
+        match purge_record(client, &record.user_id, &record.id).await {
+            Ok(()) => purged += 1,
+            Err(e) => error!("{}", e),
+        }
+    }
+    Ok(purged)
+}
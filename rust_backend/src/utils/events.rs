@@ -0,0 +1,42 @@
+use crate::models::event::VaultEvent;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before a slow stream starts missing them
+const CHANNEL_CAPACITY: usize = 256;
+
+/// The process-wide event bus: one channel per user with open streams, so a
+/// busy user can only make their own streams fall behind. It's a static rather
+/// than app data because `log_event` and the background tasks publish without
+/// a request at hand.
+fn channels() -> MutexGuard<'static, HashMap<String, broadcast::Sender<VaultEvent>>> {
+    static CHANNELS: OnceLock<Mutex<HashMap<String, broadcast::Sender<VaultEvent>>>> = OnceLock::new();
+    CHANNELS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Sends an event to every open stream of the user. Nothing is kept for
+/// devices that aren't connected; they catch up through delta sync.
+pub fn publish(user_id: &str, event: VaultEvent) {
+    let mut channels = channels();
+    if let Some(sender) = channels.get(user_id) {
+        // Fails only when the user's last stream has closed
+        if sender.send(event).is_err() {
+            channels.remove(user_id);
+        }
+    }
+}
+
+/// Opens a receiver for the user's events.
+pub fn subscribe(user_id: &str) -> broadcast::Receiver<VaultEvent> {
+    let mut channels = channels();
+    // Channels whose streams have all closed are dropped here as well as on publish
+    channels.retain(|_, sender| sender.receiver_count() > 0);
+    channels
+        .entry(user_id.to_string())
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe()
+}
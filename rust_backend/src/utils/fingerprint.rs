@@ -0,0 +1,253 @@
+use crate::config::config::Config;
+use crate::models::device::DeviceFingerprint;
+use actix_web::HttpRequest;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Header carrying the client-reported hardware hash
+pub const HARDWARE_HEADER: &str = "X-Device-Fingerprint";
+
+/// How strictly a request's fingerprint must match the one stored at approval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FingerprintPolicy {
+    /// Any mismatch denies access (the README's zero-trust rule)
+    Strict,
+    /// Hardware must match; network and TLS changes are only logged
+    Standard,
+    /// Mismatches are logged but never deny access
+    Monitor,
+}
+
+impl FromStr for FingerprintPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(FingerprintPolicy::Strict),
+            "standard" => Ok(FingerprintPolicy::Standard),
+            "monitor" => Ok(FingerprintPolicy::Monitor),
+            other => Err(format!("Unknown fingerprint policy '{}'", other)),
+        }
+    }
+}
+
+/// A fingerprint component that differs from the stored one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FingerprintMismatch {
+    Hardware,
+    /// The device never sent a hardware hash, so it is only bound by network and TLS
+    HardwareMissing,
+    Network,
+    Tls,
+}
+
+impl FingerprintPolicy {
+    /// Whether these mismatches are enough to reject the request.
+    /// Only Strict refuses a device that sends no hardware hash at all.
+    pub fn denies(&self, mismatches: &[FingerprintMismatch]) -> bool {
+        match self {
+            FingerprintPolicy::Strict => !mismatches.is_empty(),
+            FingerprintPolicy::Standard => mismatches.contains(&FingerprintMismatch::Hardware),
+            FingerprintPolicy::Monitor => false,
+        }
+    }
+}
+
+/// Fingerprint details observed on a single request
+#[derive(Debug, Clone)]
+pub struct ObservedFingerprint {
+    pub hardware_hash: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub tls_fingerprint: Option<String>,
+}
+
+/// **Collects the fingerprint of the device making a request**
+/// Proxy-supplied headers (forwarded IP, TLS fingerprint) are only read when configured.
+pub fn observe(req: &HttpRequest, config: &Config) -> ObservedFingerprint {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let ip = if config.trust_proxy_headers {
+        req.connection_info()
+            .realip_remote_addr()
+            .and_then(parse_ip)
+    } else {
+        req.peer_addr().map(|addr| addr.ip())
+    };
+
+    ObservedFingerprint {
+        hardware_hash: header(HARDWARE_HEADER),
+        ip,
+        tls_fingerprint: config.tls_fingerprint_header.as_deref().and_then(header),
+    }
+}
+
+/// Parses "1.2.3.4", "1.2.3.4:5678", "::1" or "[::1]:5678"
+fn parse_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<std::net::SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// **Turns an observation into the fingerprint stored at approval time**
+/// The source IP is widened to the configured prefix length. A client that
+/// sends no hardware hash is still bound to its network and TLS fingerprint;
+/// `None` only when nothing at all was observed.
+pub fn to_stored(observed: &ObservedFingerprint, config: &Config) -> Option<DeviceFingerprint> {
+    let fingerprint = DeviceFingerprint {
+        hardware_hash: observed.hardware_hash.clone(),
+        ip_network: observed.ip.map(|ip| network_for(ip, config)),
+        tls_fingerprint: observed.tls_fingerprint.clone(),
+    };
+    let empty = fingerprint.hardware_hash.is_none()
+        && fingerprint.ip_network.is_none()
+        && fingerprint.tls_fingerprint.is_none();
+    (!empty).then_some(fingerprint)
+}
+
+/// CIDR block containing `ip`, using the configured IPv4/IPv6 prefix lengths
+pub fn network_for(ip: IpAddr, config: &Config) -> String {
+    let prefix = match ip {
+        IpAddr::V4(_) => config.fingerprint_ipv4_prefix,
+        IpAddr::V6(_) => config.fingerprint_ipv6_prefix,
+    };
+    let network = match ip {
+        IpAddr::V4(v4) => IpAddr::V4(mask_v4(u32::from(v4), prefix).into()),
+        IpAddr::V6(v6) => IpAddr::V6(mask_v6(u128::from(v6), prefix).into()),
+    };
+    format!("{}/{}", network, prefix)
+}
+
+fn mask_v4(addr: u32, prefix: u8) -> u32 {
+    if prefix == 0 {
+        0
+    } else {
+        addr & (u32::MAX << (32 - prefix.min(32) as u32))
+    }
+}
+
+fn mask_v6(addr: u128, prefix: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        addr & (u128::MAX << (128 - prefix.min(128) as u32))
+    }
+}
+
+/// Whether `ip` falls inside the CIDR block `network`
+pub fn network_contains(network: &str, ip: IpAddr) -> bool {
+    let (base, prefix) = match network.split_once('/') {
+        Some((base, prefix)) => (base, prefix.parse::<u8>().ok()),
+        None => (network, None),
+    };
+    match (base.parse::<IpAddr>(), ip) {
+        (Ok(IpAddr::V4(base)), IpAddr::V4(ip)) => {
+            let prefix = prefix.unwrap_or(32);
+            mask_v4(u32::from(base), prefix) == mask_v4(u32::from(ip), prefix)
+        }
+        (Ok(IpAddr::V6(base)), IpAddr::V6(ip)) => {
+            let prefix = prefix.unwrap_or(128);
+            mask_v6(u128::from(base), prefix) == mask_v6(u128::from(ip), prefix)
+        }
+        _ => false,
+    }
+}
+
+/// **Lists the components of `observed` that don't match `stored`**
+/// Network and TLS are only compared when recorded at approval time. The
+/// hardware hash always is: a device bound with one must keep sending it, and
+/// one that never sent it is reported as `HardwareMissing`.
+pub fn compare(stored: &DeviceFingerprint, observed: &ObservedFingerprint) -> Vec<FingerprintMismatch> {
+    let mut mismatches = Vec::new();
+
+    match (&stored.hardware_hash, &observed.hardware_hash) {
+        (None, None) => mismatches.push(FingerprintMismatch::HardwareMissing),
+        (stored, observed) if stored != observed => mismatches.push(FingerprintMismatch::Hardware),
+        _ => {}
+    }
+    if let Some(network) = &stored.ip_network {
+        if !observed.ip.is_some_and(|ip| network_contains(network, ip)) {
+            mismatches.push(FingerprintMismatch::Network);
+        }
+    }
+    if let Some(tls) = &stored.tls_fingerprint {
+        if observed.tls_fingerprint.as_deref() != Some(tls.as_str()) {
+            mismatches.push(FingerprintMismatch::Tls);
+        }
+    }
+
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored() -> DeviceFingerprint {
+        DeviceFingerprint {
+            hardware_hash: Some("hw-1".to_string()),
+            ip_network: Some("192.168.1.0/24".to_string()),
+            tls_fingerprint: Some("ja3-1".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_network_contains() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(network_contains("192.168.1.0/24", ip("192.168.1.77")));
+        assert!(!network_contains("192.168.1.0/24", ip("192.168.2.1")));
+        assert!(network_contains("10.0.0.5/32", ip("10.0.0.5")));
+        assert!(network_contains("2001:db8::/64", ip("2001:db8::1234")));
+        assert!(!network_contains("2001:db8::/64", ip("192.168.1.1")));
+    }
+
+    #[test]
+    fn test_compare_and_policy() {
+        let mut observed = ObservedFingerprint {
+            hardware_hash: Some("hw-1".to_string()),
+            ip: Some("192.168.1.20".parse().unwrap()),
+            tls_fingerprint: Some("ja3-1".to_string()),
+        };
+        assert!(compare(&stored(), &observed).is_empty());
+
+        observed.ip = Some("203.0.113.9".parse().unwrap());
+        let mismatches = compare(&stored(), &observed);
+        assert_eq!(mismatches, vec![FingerprintMismatch::Network]);
+        assert!(FingerprintPolicy::Strict.denies(&mismatches));
+        assert!(!FingerprintPolicy::Standard.denies(&mismatches));
+
+        observed.hardware_hash = None;
+        let mismatches = compare(&stored(), &observed);
+        assert!(FingerprintPolicy::Standard.denies(&mismatches));
+        assert!(!FingerprintPolicy::Monitor.denies(&mismatches));
+    }
+
+    #[test]
+    fn test_bound_without_hardware_header() {
+        let headerless = ObservedFingerprint {
+            hardware_hash: None,
+            ip: Some("192.168.1.20".parse().unwrap()),
+            tls_fingerprint: None,
+        };
+        let stored = DeviceFingerprint {
+            hardware_hash: None,
+            ip_network: Some("192.168.1.0/24".to_string()),
+            tls_fingerprint: None,
+        };
+
+        // Still bound to its network
+        let moved = ObservedFingerprint { ip: Some("203.0.113.9".parse().unwrap()), ..headerless.clone() };
+        assert!(compare(&stored, &moved).contains(&FingerprintMismatch::Network));
+
+        // Never sending the header is only refused under Strict
+        let mismatches = compare(&stored, &headerless);
+        assert_eq!(mismatches, vec![FingerprintMismatch::HardwareMissing]);
+        assert!(FingerprintPolicy::Strict.denies(&mismatches));
+        assert!(!FingerprintPolicy::Standard.denies(&mismatches));
+    }
+}
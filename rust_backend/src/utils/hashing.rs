@@ -2,6 +2,8 @@ use argon2::password_hash::{
     rand_core::OsRng, Error as PasswordHashError, PasswordHash, SaltString,
 };
 use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use sha2::{Digest, Sha256};
+use crate::utils::key_management::encode_hex;
 
 /// **Securely hash a password using Argon2id**
 pub fn hash_password(password: &str) -> Result<String, String> {
@@ -32,3 +34,62 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, String> {
         Err(e) => Err(format!("Password verification failed: {}", e)), // Other errors
     }
 }
+
+/// **Hash a high-entropy token (e.g. a refresh token) with SHA-256**
+/// Random tokens don't need a slow KDF, and a deterministic hash can be looked up.
+pub fn hash_token(token: &str) -> String {
+    encode_hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Minimum accepted length for account passwords
+pub const MIN_PASSWORD_LENGTH: usize = 12;
+
+/// **Reject empty or weak passwords before they are hashed**
+pub fn check_password_strength(password: &str) -> Result<(), String> {
+    if password.trim().is_empty() {
+        return Err("Password cannot be empty.".to_string());
+    }
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "Password must be at least {} characters long.",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+
+    // Require at least three of: lowercase, uppercase, digits, symbols
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|present| **present).count() < 3 {
+        return Err(
+            "Password must mix at least three of: lowercase, uppercase, digits, symbols."
+                .to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify_password() {
+        let hash = hash_password("Correct-Horse-42").expect("Failed to hash password");
+        assert!(hash.starts_with("$argon2id$"));
+        assert_eq!(verify_password("Correct-Horse-42", &hash), Ok(true));
+        assert_eq!(verify_password("wrong-password", &hash), Ok(false));
+    }
+
+    #[test]
+    fn test_check_password_strength() {
+        assert!(check_password_strength("").is_err());
+        assert!(check_password_strength("Short1!").is_err());
+        assert!(check_password_strength("alllowercaseletters").is_err());
+        assert!(check_password_strength("Correct-Horse-42").is_ok());
+    }
+}
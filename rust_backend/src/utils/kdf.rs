@@ -0,0 +1,226 @@
+use argon2::Params;
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use log::warn;
+use mongodb::{
+    bson::{doc, from_document, DateTime},
+    Database,
+};
+use serde::Deserialize;
+use sha2::Sha256;
+use crate::config::config::Config;
+use crate::db::collections::{get_kdf_policies_collection, get_users_collection, is_duplicate_key_error};
+use crate::models::key::{KdfParams, KdfPolicy};
+use crate::utils::key_management::{decode_hex, encode_hex, generate_kdf_salt};
+
+pub const KDF_ALGORITHM: &str = "argon2id";
+/// Floors from the OWASP Argon2id recommendation; weaker settings are refused
+pub const MIN_KDF_MEMORY_KIB: u32 = 19 * 1024;
+pub const MIN_KDF_ITERATIONS: u32 = 2;
+pub const MAX_KDF_PARALLELISM: u32 = 16;
+const KDF_SALT_LEN: usize = 16;
+
+fn params_with_salt(policy: &KdfPolicy, salt: &[u8]) -> KdfParams {
+    KdfParams {
+        algorithm: KDF_ALGORITHM.to_string(),
+        salt: encode_hex(salt),
+        memory_kib: policy.memory_kib,
+        iterations: policy.iterations,
+        parallelism: policy.parallelism,
+    }
+}
+
+/// The costs new accounts currently get
+fn configured_policy(config: &Config) -> KdfPolicy {
+    KdfPolicy {
+        memory_kib: config.kdf_memory_kib,
+        iterations: config.kdf_iterations,
+        parallelism: config.kdf_parallelism,
+        created_at: DateTime::now(),
+    }
+}
+
+/// Fresh parameters for a new account: a random salt and the configured costs.
+pub fn new_kdf_params(config: &Config) -> KdfParams {
+    params_with_salt(&configured_policy(config), &generate_kdf_salt())
+}
+
+fn keyed_hash(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Subkey for decoy parameters, so the server key itself is never used for them
+fn decoy_key(server_key: &[u8]) -> [u8; 32] {
+    keyed_hash(server_key, &[b"valutx kdf decoy key"])
+}
+
+/// **Picks the issued policy a decoy username reports**
+/// Rendezvous hashing: each policy is scored by the keyed hash of its costs and
+/// the username, and the highest score wins. A newly added policy only takes
+/// the usernames it outscores, so most decoys keep their costs across a config
+/// change, the same as existing accounts.
+fn pick_policy<'a>(key: &[u8], policies: &'a [KdfPolicy], username: &str) -> Option<&'a KdfPolicy> {
+    policies.iter().max_by_key(|policy| {
+        let costs = format!("{}:{}:{}:", policy.memory_kib, policy.iterations, policy.parallelism);
+        keyed_hash(key, &[b"policy:", costs.as_bytes(), username.as_bytes()])
+    })
+}
+
+/// **Parameters returned for a username that doesn't exist**
+/// The salt and costs are keyed on the username, so asking twice gives the same
+/// answer. The costs are one of the issued `policies`, so the response can't be
+/// told apart from an account created under any of them.
+pub fn decoy_kdf_params(config: &Config, policies: &[KdfPolicy], username: &str) -> KdfParams {
+    let key = decoy_key(&config.server_encryption_key);
+    let salt = keyed_hash(&key, &[b"salt:", username.as_bytes()]);
+    let mut candidates = policies.to_vec();
+    candidates.push(configured_policy(config));
+    let policy = pick_policy(&key, &candidates, username).unwrap_or(&candidates[0]);
+    params_with_salt(policy, &salt[..KDF_SALT_LEN])
+}
+
+#[derive(Deserialize)]
+struct KdfCosts {
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+/// **Records the configured costs and every set found on accounts as issued policies**
+/// Run at startup, so a config change adds the new costs while the old ones,
+/// still held by existing accounts, stay available to decoys.
+pub async fn record_kdf_policies(db: &Database, config: &Config) -> Result<(), String> {
+    let configured = configured_policy(config);
+    let mut issued = vec![KdfCosts {
+        memory_kib: configured.memory_kib,
+        iterations: configured.iterations,
+        parallelism: configured.parallelism,
+    }];
+    let mut groups = get_users_collection(db)
+        .aggregate(vec![
+            doc! { "$match": { "kdf": { "$ne": null } } },
+            doc! { "$group": { "_id": {
+                "memory_kib": "$kdf.memory_kib",
+                "iterations": "$kdf.iterations",
+                "parallelism": "$kdf.parallelism",
+            } } },
+        ])
+        .await
+        .map_err(|e| format!("Failed to collect account KDF costs: {}", e))?;
+    while let Some(group) = groups
+        .try_next()
+        .await
+        .map_err(|e| format!("Failed to collect account KDF costs: {}", e))?
+    {
+        match group.get_document("_id").ok().cloned().map(from_document::<KdfCosts>) {
+            Some(Ok(costs)) => issued.push(costs),
+            _ => warn!("Skipping malformed account KDF costs: {}", group),
+        }
+    }
+
+    let policies = get_kdf_policies_collection(db);
+    for costs in issued {
+        let result = policies
+            .update_one(
+                doc! {
+                    "memory_kib": costs.memory_kib,
+                    "iterations": costs.iterations,
+                    "parallelism": costs.parallelism,
+                },
+                doc! { "$setOnInsert": { "created_at": DateTime::now() } },
+            )
+            .upsert(true)
+            .await;
+        match result {
+            // Another instance recorded it first
+            Err(e) if is_duplicate_key_error(&e) => {}
+            Err(e) => return Err(format!("Failed to record KDF policy: {}", e)),
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Every issued KDF policy, oldest first.
+pub async fn load_kdf_policies(db: &Database) -> Result<Vec<KdfPolicy>, String> {
+    get_kdf_policies_collection(db)
+        .find(doc! {})
+        .sort(doc! { "created_at": 1 })
+        .await
+        .map_err(|e| format!("Failed to load KDF policies: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to load KDF policies: {}", e))
+}
+
+impl KdfParams {
+    /// Checks the parameters are Argon2id, at or above the floors, and accepted by argon2.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.algorithm != KDF_ALGORITHM {
+            return Err(format!("Validation Error: KDF must be {}.", KDF_ALGORITHM));
+        }
+        let salt = decode_hex(&self.salt)
+            .map_err(|_| "Validation Error: KDF salt must be hex.".to_string())?;
+        if salt.len() != KDF_SALT_LEN {
+            return Err(format!("Validation Error: KDF salt must be {} bytes.", KDF_SALT_LEN));
+        }
+        if self.memory_kib < MIN_KDF_MEMORY_KIB
+            || self.iterations < MIN_KDF_ITERATIONS
+            || self.parallelism > MAX_KDF_PARALLELISM
+        {
+            return Err("Validation Error: KDF parameters are too weak.".to_string());
+        }
+        Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map(|_| ())
+            .map_err(|e| format!("Validation Error: Invalid Argon2id parameters: {}.", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kdf_params_validate() {
+        let params = KdfParams {
+            algorithm: KDF_ALGORITHM.to_string(),
+            salt: "00".repeat(KDF_SALT_LEN),
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 4,
+        };
+        assert!(params.validate().is_ok());
+        assert!(KdfParams { algorithm: "pbkdf2".to_string(), ..params.clone() }.validate().is_err());
+        assert!(KdfParams { salt: "00".repeat(8), ..params.clone() }.validate().is_err());
+        assert!(KdfParams { memory_kib: 1024, ..params.clone() }.validate().is_err());
+        assert!(KdfParams { parallelism: 0, ..params }.validate().is_err());
+    }
+
+    #[test]
+    fn test_pick_policy() {
+        let policy = |memory_kib| KdfPolicy {
+            memory_kib,
+            iterations: 3,
+            parallelism: 4,
+            created_at: DateTime::from_millis(0),
+        };
+        let key = decoy_key(&[0u8; 32]);
+        let old = [policy(19 * 1024), policy(64 * 1024)];
+        let new = [policy(19 * 1024), policy(64 * 1024), policy(128 * 1024)];
+
+        let mut moved = 0;
+        for i in 0..200 {
+            let username = format!("user{}", i);
+            let before = pick_policy(&key, &old, &username).unwrap();
+            let after = pick_policy(&key, &new, &username).unwrap();
+            // A decoy either keeps its costs or moves to the new policy, never between old ones
+            assert!(after == before || *after == new[2]);
+            moved += usize::from(after != before);
+        }
+        assert!(moved > 0 && moved < 200);
+    }
+}
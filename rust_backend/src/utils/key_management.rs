@@ -1,5 +1,65 @@
 use rand::Rng;
 
 pub fn generate_key() -> [u8; 32] {
-    rand::thread_rng().gen()
+    rand::rng().random()
+}
+
+/// 128-bit salt for master key derivation
+pub fn generate_kdf_salt() -> [u8; 16] {
+    rand::rng().random()
+}
+
+/// 160-bit shared secret for TOTP, the size RFC 4226 recommends for HMAC-SHA1
+pub fn generate_totp_secret() -> [u8; 20] {
+    rand::rng().random()
+}
+
+/// Alphabet for human-typed codes: no 0/O or 1/I/L to misread
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+fn random_code(len: usize) -> String {
+    let mut rng = rand::rng();
+    (0..len)
+        .map(|_| CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Random recovery code formatted as two groups of five, e.g. `K7M2Q-XW9RT`
+pub fn generate_recovery_code() -> String {
+    let chars = random_code(10);
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+/// Random device pairing code formatted as two groups of four, e.g. `H4QZ-8MWE`
+pub fn generate_pairing_code() -> String {
+    let chars = random_code(8);
+    format!("{}-{}", &chars[..4], &chars[4..])
+}
+
+/// Uppercases and strips separators so `k7m2q xw9rt` matches `K7M2Q-XW9RT`.
+pub fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Lowercase hex encoding for keys, hashes and identifiers
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes a hex string, e.g. a key supplied through the environment
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) {
+        return Err("Hex string must have an even length".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("Invalid hex at position {}", i))
+        })
+        .collect()
 }
@@ -1,12 +1,14 @@
 use chrono::Utc;
-use log::info;
+use log::error;
 use mongodb::{bson::doc, Client};
+use crate::models::event::VaultEvent;
+use crate::utils::events::publish;
 
 pub async fn log_event(client: &Client, user_id: &str, event_type: &str, details: &str) {
     let db = client.database("valutx");
     let collection = db.collection("logs");
 
-    collection
+    if let Err(e) = collection
         .insert_one(
             doc! {
                 "user_id": user_id,
@@ -14,20 +16,15 @@ pub async fn log_event(client: &Client, user_id: &str, event_type: &str, details
                 "event_type": event_type,
                 "details": details
             },
-            None,
         )
         .await
-        .unwrap();
-}
-
-/// Asynchronous logging function
-pub async fn log_auth_attempt(username: &str, success: bool) -> Result<(), String> {
-    // Example async logging (e.g., writing to DB or file)
-    let status = if success { "SUCCESS" } else { "FAILURE" };
-    info!("User '{}' attempted to log in: {}", username, status);
+    {
+        error!("Failed to write audit log entry: {}", e);
+    }
 
-    // Simulate async operation if needed (remove if unnecessary)
-    // tokio::time::sleep(std::time::Duration::from_millis(10)).await;
-
-    Ok(())
+    publish(
+        user_id,
+        VaultEvent::Security { event_type: event_type.to_string(), details: details.to_string() },
+    );
 }
+
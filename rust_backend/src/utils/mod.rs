@@ -1,4 +1,9 @@
 pub mod encryption;
+pub mod events;
+pub mod fingerprint;
 pub mod hashing;
+pub mod kdf;
 pub mod key_management;
-pub mod logger;
\ No newline at end of file
+pub mod logger;
+pub mod token;
+pub mod totp;
\ No newline at end of file
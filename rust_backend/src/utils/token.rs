@@ -0,0 +1,66 @@
+use crate::models::device::Device;
+use crate::utils::key_management::{encode_hex, generate_key};
+use chrono::Utc;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+
+/// **Issue an HS256 session token for a user/device pair**
+/// `jti` identifies the token in the sessions collection.
+pub fn issue_token(
+    user_id: &str,
+    device_id: &str,
+    jti: &str,
+    secret: &str,
+    ttl_secs: i64,
+) -> Result<String, String> {
+    let claims = Device {
+        device_id: device_id.to_string(),
+        expiration: (Utc::now().timestamp() + ttl_secs) as usize,
+        user_id: user_id.to_string(),
+        jti: jti.to_string(),
+    };
+
+    encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .map_err(|e| format!("Token encoding failed: {}", e))
+}
+
+/// **Decode and validate an HS256 session token**
+pub fn decode_token(token: &str, secret: &str) -> Result<Device, String> {
+    decode::<Device>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .map(|data| data.claims)
+    .map_err(|e| format!("Token validation failed: {}", e))
+}
+
+/// **Generate an opaque refresh token (256 random bits, hex encoded)**
+pub fn generate_refresh_token() -> String {
+    encode_hex(&generate_key())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_and_decode_token() {
+        let token = issue_token("user-1", "device-1", "jti-1", "secret", 60).expect("Failed to issue token");
+        let claims = decode_token(&token, "secret").expect("Failed to decode token");
+
+        assert_eq!(claims.user_id, "user-1");
+        assert_eq!(claims.device_id, "device-1");
+        assert_eq!(claims.jti, "jti-1");
+        assert!(decode_token(&token, "other-secret").is_err());
+    }
+
+    #[test]
+    fn test_expired_token_rejected() {
+        let token = issue_token("user-1", "device-1", "jti-1", "secret", -3600).expect("Failed to issue token");
+        assert!(decode_token(&token, "secret").is_err());
+    }
+}